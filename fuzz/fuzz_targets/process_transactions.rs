@@ -0,0 +1,132 @@
+//! Feeds random sequences of input records to the engine, checking that it never panics and
+//! that every account keeps its total funds equal to the sum of the available and held funds.
+//!
+//! Run with `cargo fuzz run process_transactions` from the repository root.
+
+#![no_main]
+
+use libfuzzer_sys::arbitrary::{self, Arbitrary};
+use libfuzzer_sys::fuzz_target;
+use transact::{Amount, EngineConfig, InputConfig, OperationInput, TransactionEngine};
+
+const OPERATIONS: [&str; 6] = [
+    "deposit",
+    "withdrawal",
+    "dispute",
+    "resolve",
+    "chargeback",
+    "unlock",
+];
+
+/// Amount column of a generated record, mostly a well-formed decimal, so the records get
+/// past the parsing and reach the engine
+#[derive(Arbitrary, Debug)]
+enum FuzzAmount {
+    Missing,
+    Decimal { units: i64, decimals: u8 },
+    Text(String),
+}
+
+impl FuzzAmount {
+    fn to_text(&self) -> Option<String> {
+        match self {
+            FuzzAmount::Missing => None,
+            FuzzAmount::Decimal { units, decimals } => {
+                let scale = 10i128.pow(u32::from(decimals % 8));
+                let units = i128::from(*units);
+                let fraction = units.unsigned_abs() % scale as u128;
+                let width = usize::from(decimals % 8);
+                Some(match width {
+                    0 => units.to_string(),
+                    _ => format!("{}.{:0width$}", units / scale, fraction),
+                })
+            }
+            FuzzAmount::Text(text) => Some(text.clone()),
+        }
+    }
+}
+
+/// Converts a generated limit or fee to an amount of hundredths, so it's comparable with the
+/// amounts of the records
+fn limit_amount(hundredths: u16) -> Amount {
+    format!("{}.{:02}", hundredths / 100, hundredths % 100)
+        .parse()
+        .unwrap()
+}
+
+/// A generated input record, with the client and transaction IDs drawn from a small range,
+/// so the disputes tend to find the transactions they refer to
+#[derive(Arbitrary, Debug)]
+struct FuzzRecord {
+    operation: u8,
+    client: u8,
+    tx: u8,
+    amount: FuzzAmount,
+    currency: Option<bool>,
+}
+
+impl FuzzRecord {
+    fn to_input(&self) -> OperationInput {
+        OperationInput {
+            op_type: OPERATIONS[usize::from(self.operation) % OPERATIONS.len()].to_string(),
+            client: u16::from(self.client % 8),
+            tx: u32::from(self.tx % 32),
+            amount: self.amount.to_text(),
+            currency: self
+                .currency
+                .map(|usd| if usd { "USD" } else { "EUR" }.to_string()),
+            timestamp: None,
+        }
+    }
+}
+
+/// A generated run: the engine behavior toggles and limits, followed by the records
+#[derive(Arbitrary, Debug)]
+struct FuzzRun {
+    allow_withdrawal_disputes: bool,
+    defer_early_disputes: bool,
+    create_on_withdrawal: bool,
+    settle_locked_accounts: bool,
+    allow_unlock: bool,
+    idempotent: bool,
+    overdraft: u16,
+    max_client_total: Option<u16>,
+    dispute_window: Option<u8>,
+    withdrawal_fee: Option<u16>,
+    fee_client: u8,
+    records: Vec<FuzzRecord>,
+}
+
+fuzz_target!(|run: FuzzRun| {
+    let config = EngineConfig::new()
+        .allow_withdrawal_disputes(run.allow_withdrawal_disputes)
+        .defer_early_disputes(run.defer_early_disputes)
+        .create_on_withdrawal(run.create_on_withdrawal)
+        .settle_locked_accounts(run.settle_locked_accounts)
+        .allow_unlock(run.allow_unlock)
+        .idempotent(run.idempotent)
+        .overdraft(limit_amount(run.overdraft))
+        .max_client_total(run.max_client_total.map(limit_amount))
+        .dispute_window(run.dispute_window.map(usize::from))
+        .withdrawal_fee(run.withdrawal_fee.map(limit_amount))
+        .fee_client(u16::from(run.fee_client % 8));
+    let mut engine = TransactionEngine::with_config(config);
+    let input_config = InputConfig::new();
+    for record in &run.records {
+        let input = record.to_input();
+        let (Ok(transaction), Ok(currency)) =
+            (input.to_transaction_with(&input_config), input.currency())
+        else {
+            continue;
+        };
+        // The rejections are expected, only a panic or a broken invariant is a failure
+        let _ = engine.process_in(transaction, currency);
+        for client in engine.accounts() {
+            assert!(
+                client.check_invariant(),
+                "balance invariant broken after {input:?}: {client:?}"
+            );
+        }
+    }
+    assert!(engine.validate().is_ok());
+});
@@ -0,0 +1,139 @@
+use crate::{Amount, Client, Currency, Money};
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use std::collections::HashMap;
+
+/// Key of a client account: the client ID together with the account currency,
+/// None standing for the default currency
+pub(crate) type AccountKey = (u16, Option<Currency>);
+
+/// Client accounts of the engine, stored in a list indexed by the account key.
+/// The accounts are never removed, so their positions in the list stay valid, which lets the
+/// table remember the last account looked up: the transactions of a client tend to come in
+/// bursts, e.g. a deposit followed by its dispute, and those skip the hashing of the key.
+#[derive(Debug, Default)]
+pub(crate) struct AccountTable<M = Amount> {
+    accounts: Vec<Client<M>>,
+    index: HashMap<AccountKey, usize>,
+    /// The last account looked up for a change, with its position in the list
+    last: Option<(AccountKey, usize)>,
+}
+
+impl<M> AccountTable<M> {
+    /// Returns the number of the accounts
+    pub(crate) fn len(&self) -> usize {
+        self.accounts.len()
+    }
+
+    /// Returns the number of the accounts the table can hold without reallocating
+    pub(crate) fn capacity(&self) -> usize {
+        self.accounts.capacity()
+    }
+
+    /// Reserves the room for at least the given number of additional accounts
+    pub(crate) fn reserve(&mut self, additional: usize) {
+        self.accounts.reserve(additional);
+        self.index.reserve(additional);
+    }
+
+    /// Checks whether there's an account with the given key
+    pub(crate) fn contains_key(&self, key: &AccountKey) -> bool {
+        self.index.contains_key(key)
+    }
+
+    /// Returns the account with the given key, if it exists
+    pub(crate) fn get(&self, key: &AccountKey) -> Option<&Client<M>> {
+        self.index
+            .get(key)
+            .map(|&position| &self.accounts[position])
+    }
+
+    /// Returns the account with the given key for a change, if it exists, remembering it for
+    /// the next lookup.
+    pub(crate) fn get_mut(&mut self, key: &AccountKey) -> Option<&mut Client<M>> {
+        let position = match self.last {
+            Some((last_key, position)) if last_key == *key => position,
+            _ => {
+                let position = *self.index.get(key)?;
+                self.last = Some((*key, position));
+                position
+            }
+        };
+        Some(&mut self.accounts[position])
+    }
+
+    /// Adds the account, keyed by its client ID and currency, replacing an existing one
+    /// with the same key. Returns the added account.
+    pub(crate) fn insert(&mut self, client: Client<M>) -> &mut Client<M> {
+        let key = (client.id, client.currency);
+        let position = match self.index.get(&key) {
+            Some(&position) => {
+                self.accounts[position] = client;
+                position
+            }
+            None => {
+                self.accounts.push(client);
+                self.index.insert(key, self.accounts.len() - 1);
+                self.accounts.len() - 1
+            }
+        };
+        self.last = Some((key, position));
+        &mut self.accounts[position]
+    }
+
+    /// Returns an iterator over the accounts, in no particular order
+    pub(crate) fn iter(&self) -> impl Iterator<Item = &Client<M>> {
+        self.accounts.iter()
+    }
+}
+
+impl<M> IntoIterator for AccountTable<M> {
+    type Item = Client<M>;
+    type IntoIter = std::vec::IntoIter<Client<M>>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.accounts.into_iter()
+    }
+}
+
+impl<M> Extend<Client<M>> for AccountTable<M> {
+    fn extend<I: IntoIterator<Item = Client<M>>>(&mut self, clients: I) {
+        for client in clients {
+            self.insert(client);
+        }
+    }
+}
+
+impl<M> FromIterator<Client<M>> for AccountTable<M> {
+    fn from_iter<I: IntoIterator<Item = Client<M>>>(clients: I) -> Self {
+        let mut table = AccountTable {
+            accounts: Vec::new(),
+            index: HashMap::new(),
+            last: None,
+        };
+        table.extend(clients);
+        table
+    }
+}
+
+/// Writes the accounts as a map keyed by the client ID, followed by the currency if there's one,
+/// e.g. `"1"` or `"1/USD"`, so the snapshots of single-currency engines keep their format.
+impl<M: Serialize> Serialize for AccountTable<M> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_map(self.accounts.iter().map(|client| {
+            let key = match client.currency {
+                Some(currency) => format!("{}/{}", client.id, currency),
+                None => client.id.to_string(),
+            };
+            (key, client)
+        }))
+    }
+}
+
+/// Reads the accounts written by the [`Serialize`] implementation, taking the key from the
+/// account itself.
+impl<'de, M: Money + Deserialize<'de>> Deserialize<'de> for AccountTable<M> {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let clients = HashMap::<String, Client<M>>::deserialize(deserializer)?;
+        Ok(clients.into_values().collect())
+    }
+}
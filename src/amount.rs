@@ -0,0 +1,185 @@
+use serde::{de, Deserialize, Deserializer, Serialize, Serializer};
+use std::error::Error;
+use std::fmt;
+use std::str::FromStr;
+
+/// Number of decimal places kept for all the money amounts
+pub const AMOUNT_DECIMALS: usize = 4;
+/// Number of amount units in a single whole currency unit
+pub const AMOUNT_SCALE: i64 = 10_000;
+
+/// Rounding of the amounts to a lower number of decimal places
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum RoundingMode {
+    /// Drops the extra digits, rounding toward zero
+    #[default]
+    Truncate,
+    /// Rounds to the nearest value, with the ties going to the even digit (banker's rounding)
+    HalfEven,
+    /// Rounds to the nearest value, with the ties going away from zero
+    HalfUp,
+}
+
+/// Fixed-point money amount, stored as a signed count of ten-thousandths.
+/// Negative values are allowed, since some intermediate balances can go below zero.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord)]
+pub struct Amount(i64);
+
+impl Amount {
+    /// Returns true if the amount is below zero
+    pub fn is_negative(self) -> bool {
+        self.0 < 0
+    }
+
+    /// Rounds the amount to the given number of decimal places, up to four, using the given mode.
+    /// Negative values are rounded symmetrically to the positive ones.
+    pub fn round(self, decimals: usize, mode: RoundingMode) -> Amount {
+        let factor = 10i128.pow((AMOUNT_DECIMALS - decimals.min(AMOUNT_DECIMALS)) as u32);
+        let units = i128::from(self.0);
+        let (quotient, remainder) = (units.abs() / factor, units.abs() % factor);
+        let round_up = match mode {
+            RoundingMode::Truncate => false,
+            RoundingMode::HalfEven => {
+                remainder * 2 > factor || (remainder * 2 == factor && quotient % 2 == 1)
+            }
+            RoundingMode::HalfUp => remainder * 2 >= factor,
+        };
+        let rounded = (quotient + i128::from(round_up)) * factor * units.signum();
+        // Rounding away from zero can only overflow at the very edge of the range
+        i64::try_from(rounded).map_or(self, Amount)
+    }
+
+    /// Adds two amounts, returning None on overflow
+    pub fn checked_add(self, other: Amount) -> Option<Amount> {
+        self.0.checked_add(other.0).map(Amount)
+    }
+
+    /// Subtracts two amounts, returning None on overflow
+    pub fn checked_sub(self, other: Amount) -> Option<Amount> {
+        self.0.checked_sub(other.0).map(Amount)
+    }
+}
+
+/// Writes the amount with four decimal places, or with the precision given in the format
+/// specification (e.g. `{:.2}`), up to four. The extra digits are truncated.
+impl fmt::Display for Amount {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let decimals = f
+            .precision()
+            .unwrap_or(AMOUNT_DECIMALS)
+            .min(AMOUNT_DECIMALS);
+        let units = self.0.unsigned_abs() / 10u64.pow((AMOUNT_DECIMALS - decimals) as u32);
+        let sign = if self.0 < 0 && units != 0 { "-" } else { "" };
+        if decimals == 0 {
+            return write!(f, "{sign}{units}");
+        }
+        let scale = 10u64.pow(decimals as u32);
+        write!(
+            f,
+            "{}{}.{:0width$}",
+            sign,
+            units / scale,
+            units % scale,
+            width = decimals
+        )
+    }
+}
+
+/// Parses a decimal string, such as `-12.3456`, into the fixed-point form.
+/// Digits beyond the fourth decimal place are truncated.
+impl FromStr for Amount {
+    type Err = ParseAmountError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = || ParseAmountError::new(s);
+        let (negative, digits) = match s.strip_prefix('-') {
+            Some(rest) => (true, rest),
+            None => (false, s.strip_prefix('+').unwrap_or(s)),
+        };
+        let (whole, frac) = digits.split_once('.').unwrap_or((digits, ""));
+        if whole.is_empty() && frac.is_empty() {
+            return Err(invalid());
+        }
+        if !whole
+            .bytes()
+            .chain(frac.bytes())
+            .all(|b| b.is_ascii_digit())
+        {
+            return Err(invalid());
+        }
+        let mut units: i64 = 0;
+        for b in whole.bytes() {
+            units = units
+                .checked_mul(10)
+                .and_then(|u| u.checked_add(i64::from(b - b'0')))
+                .ok_or_else(invalid)?;
+        }
+        let mut frac_units: i64 = 0;
+        for i in 0..AMOUNT_DECIMALS {
+            let digit = frac.as_bytes().get(i).map_or(0, |b| i64::from(b - b'0'));
+            frac_units = frac_units * 10 + digit;
+        }
+        units = units
+            .checked_mul(AMOUNT_SCALE)
+            .and_then(|u| u.checked_add(frac_units))
+            .ok_or_else(invalid)?;
+        Ok(Amount(if negative { -units } else { units }))
+    }
+}
+
+/// Converts a floating point value, such as a JSON number, to the fixed-point form.
+/// The value is taken in its shortest decimal form, e.g. `1.0001` rather than the nearest
+/// binary fraction just below it, and its extra digits are truncated as in the parsing, so
+/// the negative values are handled symmetrically. The values out of the range of the amount,
+/// as well as NaN and the infinities, are rejected.
+impl TryFrom<f64> for Amount {
+    type Error = ParseAmountError;
+
+    fn try_from(value: f64) -> Result<Self, Self::Error> {
+        value.to_string().parse()
+    }
+}
+
+/// Writes the amount as a decimal string, or as the raw fixed-point integer in the binary
+/// formats, which aren't meant to be read by humans.
+impl Serialize for Amount {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        if serializer.is_human_readable() {
+            serializer.collect_str(self)
+        } else {
+            serializer.serialize_i64(self.0)
+        }
+    }
+}
+
+impl<'de> Deserialize<'de> for Amount {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        if !deserializer.is_human_readable() {
+            return i64::deserialize(deserializer).map(Amount);
+        }
+        let s = String::deserialize(deserializer)?;
+        s.parse().map_err(de::Error::custom)
+    }
+}
+
+/// Error returned when a string can't be parsed as an amount
+#[derive(Debug)]
+pub struct ParseAmountError {
+    input: String,
+}
+
+impl ParseAmountError {
+    fn new(input: &str) -> ParseAmountError {
+        ParseAmountError {
+            input: input.to_string(),
+        }
+    }
+}
+
+impl fmt::Display for ParseAmountError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Invalid amount '{}'", self.input)
+    }
+}
+
+impl Error for ParseAmountError {}
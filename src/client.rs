@@ -0,0 +1,47 @@
+use crate::{Amount, Currency, Money};
+use serde::{Deserialize, Serialize};
+
+/// Client data structure with support for serialized output
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct Client<M = Amount> {
+    #[serde(rename = "client")]
+    pub id: u16,
+    /// Currency of the account, or None for the default one, used when the input doesn't
+    /// specify any
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub currency: Option<Currency>,
+    pub available: M,
+    pub held: M,
+    pub total: M,
+    pub locked: bool,
+    /// Number of the transactions applied to the account, including the disputes, resolves
+    /// and chargebacks, while the rejected ones are not counted
+    #[serde(default)]
+    pub tx_count: u64,
+    /// Highest held funds of the account so far, which the disputes may have raised above the
+    /// current ones
+    #[serde(default)]
+    pub peak_held: M,
+}
+
+impl<M: Money> Client<M> {
+    /// Creates an empty, unlocked account of the client in the given currency
+    pub fn new(id: u16, currency: Option<Currency>) -> Client<M> {
+        Client {
+            id,
+            currency,
+            available: M::default(),
+            held: M::default(),
+            total: M::default(),
+            locked: false,
+            tx_count: 0,
+            peak_held: M::default(),
+        }
+    }
+
+    /// Checks the balance invariant of the account: the total funds are always
+    /// the sum of the available and the held funds.
+    pub fn check_invariant(&self) -> bool {
+        self.available.checked_add(self.held) == Some(self.total)
+    }
+}
@@ -0,0 +1,153 @@
+use crate::{Amount, Money};
+
+/// Behavior toggles of the [`TransactionEngine`](crate::TransactionEngine), built once and
+/// handed to the engine on its creation:
+///
+/// ```
+/// use transact::{EngineConfig, TransactionEngine};
+///
+/// let config = EngineConfig::new().allow_withdrawal_disputes(false);
+/// let engine = TransactionEngine::with_config(config);
+/// ```
+///
+/// The default configuration matches the behavior of an engine created with
+/// [`TransactionEngine::new`](crate::TransactionEngine::new).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct EngineConfig<M = Amount> {
+    pub(crate) allow_withdrawal_disputes: bool,
+    pub(crate) overdraft: M,
+    pub(crate) max_clients: Option<usize>,
+    pub(crate) defer_early_disputes: bool,
+    pub(crate) create_on_withdrawal: bool,
+    pub(crate) settle_locked_accounts: bool,
+    pub(crate) allow_unlock: bool,
+    pub(crate) dispute_window: Option<usize>,
+    pub(crate) idempotent: bool,
+    pub(crate) withdrawal_fee: Option<M>,
+    pub(crate) fee_client: u16,
+    pub(crate) max_client_total: Option<M>,
+}
+
+impl<M: Money> Default for EngineConfig<M> {
+    fn default() -> EngineConfig<M> {
+        EngineConfig {
+            allow_withdrawal_disputes: true,
+            overdraft: M::default(),
+            max_clients: None,
+            defer_early_disputes: false,
+            create_on_withdrawal: false,
+            settle_locked_accounts: false,
+            allow_unlock: false,
+            dispute_window: None,
+            idempotent: false,
+            withdrawal_fee: None,
+            fee_client: 0,
+            max_client_total: None,
+        }
+    }
+}
+
+impl EngineConfig {
+    /// Creates the default configuration
+    pub fn new() -> EngineConfig {
+        EngineConfig::default()
+    }
+}
+
+impl<M: Money> EngineConfig<M> {
+    /// Sets whether the withdrawals can be disputed, or only the deposits. Enabled by default.
+    pub fn allow_withdrawal_disputes(mut self, allow: bool) -> EngineConfig<M> {
+        self.allow_withdrawal_disputes = allow;
+        self
+    }
+
+    /// Sets the overdraft limit of every client account: a withdrawal is accepted as long as it
+    /// leaves the available funds at or above the negated limit. Zero by default.
+    pub fn overdraft(mut self, limit: M) -> EngineConfig<M> {
+        self.overdraft = limit;
+        self
+    }
+
+    /// Sets the maximum number of client accounts, each currency of a client counting
+    /// separately. Once reached, the deposits creating a new account are rejected, while the
+    /// existing accounts are processed as usual. Unlimited by default.
+    pub fn max_clients(mut self, limit: Option<usize>) -> EngineConfig<M> {
+        self.max_clients = limit;
+        self
+    }
+
+    /// Sets whether a dispute of a transaction not seen yet is kept until the transaction
+    /// arrives, instead of being rejected as unknown. Disabled by default.
+    ///
+    /// A parked dispute is applied right after the deposit or withdrawal it refers to, as if it
+    /// came next, and only the first dispute of a transaction is parked. The resolves and
+    /// chargebacks are never parked, so they have to come after the transaction.
+    pub fn defer_early_disputes(mut self, defer: bool) -> EngineConfig<M> {
+        self.defer_early_disputes = defer;
+        self
+    }
+
+    /// Sets whether a withdrawal for an unknown client creates its empty account, within the
+    /// limit of the accounts, before being rejected for insufficient funds, instead of being
+    /// rejected as unknown. Disabled by default, so only the deposits create the accounts.
+    pub fn create_on_withdrawal(mut self, create: bool) -> EngineConfig<M> {
+        self.create_on_withdrawal = create;
+        self
+    }
+
+    /// Sets whether the disputes, resolves and chargebacks still apply to a locked account, so
+    /// the disputes outstanding when a chargeback locked it can be settled. The deposits and
+    /// withdrawals are rejected either way. Disabled by default.
+    pub fn settle_locked_accounts(mut self, settle: bool) -> EngineConfig<M> {
+        self.settle_locked_accounts = settle;
+        self
+    }
+
+    /// Sets whether the unlock operations are accepted, reinstating the locked accounts, e.g.
+    /// after a manual review. Disabled by default, so a locked account stays locked.
+    pub fn allow_unlock(mut self, allow: bool) -> EngineConfig<M> {
+        self.allow_unlock = allow;
+        self
+    }
+
+    /// Sets the number of the most recent deposits and withdrawals kept for the disputes. Once
+    /// exceeded, the oldest ones are forgotten, so a dispute referring to them is rejected as
+    /// unknown, while the ones under dispute are kept until settled. Unlimited by default.
+    pub fn dispute_window(mut self, window: Option<usize>) -> EngineConfig<M> {
+        self.dispute_window = window;
+        self
+    }
+
+    /// Sets whether the deposits and withdrawals are idempotent: a repeat of an applied one,
+    /// with the same type, client, currency and amount, is accepted without any change, while a
+    /// different one reusing its ID is rejected. Meant for the overlapping batches of the same
+    /// transactions. Disabled by default.
+    pub fn idempotent(mut self, idempotent: bool) -> EngineConfig<M> {
+        self.idempotent = idempotent;
+        self
+    }
+
+    /// Sets the fee charged on every successful withdrawal on top of its amount, credited to
+    /// the account of the fee client in the same currency. The fee only counts in the funds
+    /// check, a dispute of the withdrawal holds its amount alone. None by default.
+    pub fn withdrawal_fee(mut self, fee: Option<M>) -> EngineConfig<M> {
+        self.withdrawal_fee = fee;
+        self
+    }
+
+    /// Sets the ID of the client collecting the withdrawal fees, 0 by default. Its account is
+    /// created by the first fee, and credited even if locked, while its own withdrawals are
+    /// free of the fee.
+    pub fn fee_client(mut self, client_id: u16) -> EngineConfig<M> {
+        self.fee_client = client_id;
+        self
+    }
+
+    /// Sets the limit of the total funds of a client account, rejecting the deposits which
+    /// would raise the total above it. The disputes, resolves and chargebacks, as well as the
+    /// withdrawal fees, are not limited. Unlimited by default.
+    pub fn max_client_total(mut self, limit: Option<M>) -> EngineConfig<M> {
+        self.max_client_total = limit;
+        self
+    }
+}
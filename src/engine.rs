@@ -0,0 +1,1029 @@
+use crate::accounts::AccountTable;
+use crate::{
+    Amount, Client, Currency, EngineConfig, EngineError, InconsistentLedger, InputConfig, Money,
+    OperationInput, Transaction, TransactionError,
+};
+use csv::{ReaderBuilder, Trim};
+use serde::{Deserialize, Serialize};
+use std::collections::hash_map::Entry;
+use std::collections::{HashMap, VecDeque};
+use std::fmt;
+use std::fs::File;
+use std::io::{self, BufReader, BufWriter, Write};
+use std::path::Path;
+
+/// Transaction processing engine, holding the state of all the client accounts
+/// together with the transactions that can be disputed or settled.
+/// The state can be saved to a snapshot file, and loaded back to continue the processing.
+///
+/// The balances are kept in the fixed-point [`Amount`] by default, see [`Money`] for using
+/// another type.
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(bound(
+    serialize = "M: Serialize",
+    deserialize = "M: Money + Deserialize<'de>"
+))]
+pub struct TransactionEngine<M = Amount> {
+    #[serde(skip)]
+    config: EngineConfig<M>, // Not part of the state, set up separately for every run
+    clients: AccountTable<M>,
+    transactions: HashMap<u32, DisputableRecord<M>>, // Keeps the disputable transactions and their state
+    /// Disputes received before the transaction they refer to, keyed by its ID
+    #[serde(default, skip_serializing_if = "HashMap::is_empty")]
+    pending_disputes: HashMap<u32, PendingDispute<M>>,
+    /// IDs of the disputable transactions in the order of their registration, oldest first,
+    /// only kept with a dispute window
+    #[serde(default, skip_serializing_if = "VecDeque::is_empty")]
+    tx_order: VecDeque<u32>,
+    /// Details of the applied deposits and withdrawals, keyed by their ID, only kept in the
+    /// idempotent mode to recognize the repeats
+    #[serde(default, skip_serializing_if = "HashMap::is_empty")]
+    applied: HashMap<u32, AppliedTransaction<M>>,
+    /// Callback of the library user, invoked after every accepted transaction
+    #[serde(skip)]
+    observer: Option<Observer<M>>,
+}
+
+/// Callback invoked with every accepted transaction and the resulting state of the account
+type ObserverFn<M> = dyn FnMut(&Transaction<M>, &Client<M>) + Send;
+
+/// Observer of the accepted transactions, see [`TransactionEngine::set_observer`]
+struct Observer<M>(Box<ObserverFn<M>>);
+
+impl<M> fmt::Debug for Observer<M> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str("Observer")
+    }
+}
+
+impl<M: Money> Default for TransactionEngine<M> {
+    fn default() -> TransactionEngine<M> {
+        TransactionEngine {
+            config: EngineConfig::default(),
+            clients: AccountTable::default(),
+            transactions: HashMap::new(),
+            pending_disputes: HashMap::new(),
+            tx_order: VecDeque::new(),
+            applied: HashMap::new(),
+            observer: None,
+        }
+    }
+}
+
+/// Dispute state of a stored transaction
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+enum TxState {
+    Normal,
+    Disputed,
+    Resolved,
+    ChargedBack,
+}
+
+impl TxState {
+    /// Checks whether a dispute can be opened for a transaction in this state.
+    /// A resolved transaction can be disputed again, while a charge back is final.
+    fn check_dispute<M>(self) -> Result<(), TransactionError<M>> {
+        match self {
+            TxState::Normal | TxState::Resolved => Ok(()),
+            TxState::Disputed => Err(TransactionError::AlreadyDisputed),
+            TxState::ChargedBack => Err(TransactionError::AlreadyChargedBack),
+        }
+    }
+
+    /// Checks whether a transaction in this state can be resolved or charged back.
+    fn check_settle<M>(self) -> Result<(), TransactionError<M>> {
+        match self {
+            TxState::Normal => Err(TransactionError::NotDisputed),
+            TxState::Disputed => Ok(()),
+            TxState::Resolved => Err(TransactionError::AlreadyResolved),
+            TxState::ChargedBack => Err(TransactionError::AlreadyChargedBack),
+        }
+    }
+}
+
+/// A deposit or withdrawal registered in the engine, keeping only what's needed to
+/// service a later dispute, together with its dispute state
+#[derive(Serialize, Deserialize, Debug)]
+struct DisputableRecord<M> {
+    client_id: u16,
+    #[serde(default)]
+    currency: Option<Currency>,
+    amount: M,
+    is_withdrawal: bool,
+    state: TxState,
+    /// Portion of the amount held by the current (or the last) dispute, if it's not the whole
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    disputed: Option<M>,
+}
+
+impl<M: Money> DisputableRecord<M> {
+    fn new(
+        client_id: u16,
+        currency: Option<Currency>,
+        amount: M,
+        is_withdrawal: bool,
+    ) -> DisputableRecord<M> {
+        DisputableRecord {
+            client_id,
+            currency,
+            amount,
+            is_withdrawal,
+            state: TxState::Normal,
+            disputed: None,
+        }
+    }
+
+    /// Returns the amount held by the dispute of the transaction
+    fn disputed_amount(&self) -> M {
+        self.disputed.unwrap_or(self.amount)
+    }
+}
+
+/// A deposit or withdrawal applied in the idempotent mode, with all its details, so a repeat
+/// can be told apart from a different transaction reusing the ID
+#[derive(Serialize, Deserialize, Debug, PartialEq, Eq)]
+struct AppliedTransaction<M> {
+    client_id: u16,
+    #[serde(default)]
+    currency: Option<Currency>,
+    amount: M,
+    is_withdrawal: bool,
+}
+
+impl<M: Money> AppliedTransaction<M> {
+    /// Returns the details of a deposit or withdrawal, or None for the other transactions
+    fn of(
+        transaction: &Transaction<M>,
+        currency: Option<Currency>,
+    ) -> Option<AppliedTransaction<M>> {
+        let (client_id, amount, is_withdrawal) = match *transaction {
+            Transaction::Deposit(client_id, _, amount) => (client_id, amount, false),
+            Transaction::Withdrawal(client_id, _, amount) => (client_id, amount, true),
+            _ => return None,
+        };
+        Some(AppliedTransaction {
+            client_id,
+            currency,
+            amount,
+            is_withdrawal,
+        })
+    }
+}
+
+/// A transaction under dispute, holding its amount on the client account
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct OpenDispute<M = Amount> {
+    pub client_id: u16,
+    /// Currency of the account, or None for the default one
+    pub currency: Option<Currency>,
+    /// ID of the disputed deposit or withdrawal
+    pub tx_id: u32,
+    /// Amount held by the dispute, which is only a portion of the transaction for a partial one
+    pub amount: M,
+    pub is_withdrawal: bool,
+}
+
+/// A dispute parked until the transaction it refers to is processed
+#[derive(Serialize, Deserialize, Debug, Clone, Copy)]
+struct PendingDispute<M> {
+    client_id: u16,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    amount: Option<M>,
+}
+
+fn find_client_by_id<M>(
+    clients: &mut AccountTable<M>,
+    client_id: u16,
+    currency: Option<Currency>,
+) -> Option<&mut Client<M>> {
+    clients.get_mut(&(client_id, currency))
+}
+
+/// Returns the currency of the referenced transaction, which selects the account it belongs to.
+fn find_currency_by_tx_id<M>(
+    transactions: &HashMap<u32, DisputableRecord<M>>,
+    tx_id: u32,
+) -> Option<Currency> {
+    transactions.get(&tx_id).and_then(|record| record.currency)
+}
+
+/// Adds the amounts, failing the transaction on overflow.
+fn add<M: Money>(a: M, b: M) -> Result<M, TransactionError<M>> {
+    a.checked_add(b).ok_or(TransactionError::Overflow)
+}
+
+/// Subtracts the amounts, failing the transaction on overflow.
+fn sub<M: Money>(a: M, b: M) -> Result<M, TransactionError<M>> {
+    a.checked_sub(b).ok_or(TransactionError::Overflow)
+}
+
+/// Checks that the referenced transaction belongs to the client, and that the client can be altered.
+/// A locked account can still have its disputes settled if `settle_locked` is set.
+fn check_client<M>(
+    client: &Client<M>,
+    tx_client_id: u16,
+    settle_locked: bool,
+) -> Result<(), TransactionError<M>> {
+    if tx_client_id != client.id {
+        return Err(TransactionError::ClientMismatch {
+            expected: client.id,
+            actual: tx_client_id,
+        });
+    }
+    if client.locked && !settle_locked {
+        return Err(TransactionError::AccountLocked(client.id));
+    }
+    Ok(())
+}
+
+/// Attempts to perform a disputed operation on the specified client, holding the given
+/// portion of the transaction amount. Returns an error if the dispute cannot be aplied.
+///
+/// A disputed withdrawal puts the withdrawn amount back on the account as held funds, pending
+/// the reversal, so the available amount is unchanged while both held and total rise by it.
+fn apply_dispute<M: Money>(
+    client: &mut Client<M>,
+    record: &DisputableRecord<M>,
+    amount: M,
+    settle_locked: bool,
+) -> Result<(), TransactionError<M>> {
+    check_client(client, record.client_id, settle_locked)?;
+    let held = add(client.held, amount)?;
+    if record.is_withdrawal {
+        client.total = add(client.total, amount)?;
+    } else {
+        // The deposited funds may have been spent already, in which case the available
+        // amount goes negative, while the disputed amount is still held in full.
+        client.available = sub(client.available, amount)?;
+    }
+    client.held = held;
+    client.peak_held = client.peak_held.max(held);
+    Ok(())
+}
+
+/// Checks that the held funds of the client cover the disputed amount, which can only fail
+/// if the engine state is inconsistent, e.g. when built from a tampered snapshot.
+fn check_held<M: Money>(client: &Client<M>, disputed: M) -> Result<(), TransactionError<M>> {
+    if client.held < disputed {
+        return Err(TransactionError::InconsistentHeld {
+            held: client.held,
+            required: disputed,
+        });
+    }
+    Ok(())
+}
+
+/// Attempts to resolve the disputed operation on the specified client.
+/// Returns an error if the operation cannot be aplied, leaving the client unchanged.
+/// Resolving a withdrawal dispute confirms the withdrawal, dropping the held amount again.
+fn apply_resolve<M: Money>(
+    client: &mut Client<M>,
+    record: &DisputableRecord<M>,
+    settle_locked: bool,
+) -> Result<(), TransactionError<M>> {
+    check_client(client, record.client_id, settle_locked)?;
+    let disputed = record.disputed_amount();
+    check_held(client, disputed)?;
+    let held = sub(client.held, disputed)?;
+    if record.is_withdrawal {
+        client.total = sub(client.total, disputed)?;
+    } else {
+        client.available = add(client.available, disputed)?;
+    }
+    client.held = held;
+    Ok(())
+}
+
+/// Applies a chargeback operation on the specified client, reversing the given portion of the
+/// disputed amount, while the rest of it is released as in a resolve.
+/// Returns an error if the operation cannot be aplied, leaving the client unchanged.
+/// Charging back a withdrawal reverses it, releasing the held amount to the available funds.
+/// Charging back a deposit which was already spent leaves the account with negative funds.
+fn apply_chargeback<M: Money>(
+    client: &mut Client<M>,
+    record: &DisputableRecord<M>,
+    amount: M,
+    settle_locked: bool,
+) -> Result<(), TransactionError<M>> {
+    check_client(client, record.client_id, settle_locked)?;
+    let disputed = record.disputed_amount();
+    check_held(client, disputed)?;
+    let released = sub(disputed, amount)?;
+    let held = sub(client.held, disputed)?;
+    let (available, total) = if record.is_withdrawal {
+        (add(client.available, amount)?, sub(client.total, released)?)
+    } else {
+        (add(client.available, released)?, sub(client.total, amount)?)
+    };
+    client.available = available;
+    client.held = held;
+    client.total = total;
+    client.locked = true;
+    Ok(())
+}
+
+/// Processes the transactions in order with a new engine, using the default configuration,
+/// and returns the resulting client records, sorted as in [`TransactionEngine::accounts`].
+/// The rejected transactions are skipped, leaving the state unchanged. Mainly meant for
+/// exercising the business logic without going through the input parsing:
+///
+/// ```
+/// use transact::{run_transactions, Transaction};
+///
+/// let clients = run_transactions(vec![
+///     Transaction::Deposit(1, 1, "2.5".parse().unwrap()),
+///     Transaction::Withdrawal(1, 2, "1.0".parse().unwrap()),
+/// ]);
+/// assert_eq!(clients[0].available, "1.5".parse().unwrap());
+/// ```
+pub fn run_transactions<I: IntoIterator<Item = Transaction>>(transactions: I) -> Vec<Client> {
+    let mut engine = TransactionEngine::new();
+    for transaction in transactions {
+        // Rejections leave the state unchanged, same as in the binary
+        let _ = engine.process(transaction);
+    }
+    engine.accounts().cloned().collect()
+}
+
+/// Processes the CSV transaction file at the given path with a new engine, using the given
+/// configuration, and returns the resulting client records, sorted as in
+/// [`TransactionEngine::accounts`]. The file is read as the binary reads it by default: with
+/// the header, comma-separated and with the fields trimmed.
+///
+/// The records with an unknown operation or an invalid amount or currency are skipped, same as
+/// the transactions rejected by the engine, while a record that can't be parsed at all stops
+/// the processing with an error. Nothing is written to the output or logged, leaving the
+/// presentation of the accounts to the caller.
+pub fn run_from_path<P: AsRef<Path>>(
+    path: P,
+    config: EngineConfig,
+) -> Result<Vec<Client>, EngineError> {
+    let file = File::open(path).map_err(EngineError::Io)?;
+    run_from_reader(BufReader::new(file), config)
+}
+
+/// Processes the CSV transactions already in memory, the same way as [`run_from_path`] does
+/// for a file, sparing a temporary file:
+///
+/// ```
+/// use transact::{run_from_bytes, EngineConfig};
+///
+/// let csv = b"type,client,tx,amount\ndeposit,1,1,2.5\nwithdrawal,1,2,1.0\n";
+/// let clients = run_from_bytes(csv, EngineConfig::new()).unwrap();
+/// assert_eq!(clients[0].available, "1.5".parse().unwrap());
+/// ```
+pub fn run_from_bytes(bytes: &[u8], config: EngineConfig) -> Result<Vec<Client>, EngineError> {
+    run_from_reader(bytes, config)
+}
+
+/// Processes the CSV transactions of the reader, see [`run_from_path`].
+fn run_from_reader<R: io::Read>(
+    input: R,
+    config: EngineConfig,
+) -> Result<Vec<Client>, EngineError> {
+    let mut reader = ReaderBuilder::new()
+        .trim(Trim::All)
+        .flexible(true)
+        .from_reader(input);
+    let input_config = InputConfig::default();
+    let mut engine = TransactionEngine::with_config(config);
+    for record in reader.deserialize::<OperationInput>() {
+        let record = record.map_err(EngineError::from_csv)?;
+        let Ok(currency) = record.currency() else {
+            continue;
+        };
+        if let Ok(transaction) = record.to_transaction_with(&input_config) {
+            // Rejections leave the state unchanged, same as in the binary
+            let _ = engine.process_in(transaction, currency);
+        }
+    }
+    Ok(engine.accounts().cloned().collect())
+}
+
+impl TransactionEngine {
+    /// Creates an engine without any clients or transactions, keeping the balances in [`Amount`]
+    pub fn new() -> TransactionEngine {
+        TransactionEngine::default()
+    }
+}
+
+impl<M: Money> TransactionEngine<M> {
+    /// Creates an engine without any clients or transactions, using the given configuration
+    pub fn with_config(config: EngineConfig<M>) -> TransactionEngine<M> {
+        TransactionEngine {
+            config,
+            ..TransactionEngine::default()
+        }
+    }
+
+    /// Reserves the room for at least the given number of additional client accounts, along
+    /// with one disputable transaction for each of them, so a large input doesn't have to
+    /// grow the tables on the way. It has no effect on the processing otherwise.
+    pub fn reserve(&mut self, clients: usize) {
+        self.clients.reserve(clients);
+        self.transactions.reserve(clients);
+    }
+
+    /// Registers the callback invoked after every accepted transaction, with the transaction
+    /// and the resulting state of the account it was applied to, e.g. to stream the balance
+    /// changes out as they happen. It replaces the previous one, if any.
+    ///
+    /// A dispute kept until the transaction it refers to arrives, see
+    /// [`EngineConfig::defer_early_disputes`], is observed when it's applied. The observer is
+    /// not part of the saved state, and it's not handed to the shards of
+    /// [`TransactionEngine::into_shards`].
+    pub fn set_observer<F>(&mut self, observer: F)
+    where
+        F: FnMut(&Transaction<M>, &Client<M>) + Send + 'static,
+    {
+        self.observer = Some(Observer(Box::new(observer)));
+    }
+
+    /// Removes the callback registered with [`TransactionEngine::set_observer`]
+    pub fn clear_observer(&mut self) {
+        self.observer = None;
+    }
+
+    /// Returns the configuration of the engine
+    pub fn config(&self) -> &EngineConfig<M> {
+        &self.config
+    }
+
+    /// Replaces the configuration of the engine, e.g. after its state was loaded from a snapshot
+    pub fn set_config(&mut self, config: EngineConfig<M>) {
+        self.config = config;
+    }
+
+    /// Returns an iterator over the client records, sorted by the client ID, and then by the
+    /// currency, with the default currency first
+    pub fn accounts(&self) -> impl Iterator<Item = &Client<M>> {
+        let mut clients: Vec<&Client<M>> = self.clients.iter().collect();
+        clients.sort_unstable_by_key(|client| (client.id, client.currency));
+        clients.into_iter()
+    }
+
+    /// Returns the current record of a single client in the default currency, if it exists
+    pub fn get_account(&self, client_id: u16) -> Option<&Client<M>> {
+        self.get_account_in(client_id, None)
+    }
+
+    /// Returns the current record of a single client in the given currency, if it exists
+    pub fn get_account_in(&self, client_id: u16, currency: Option<Currency>) -> Option<&Client<M>> {
+        self.clients.get(&(client_id, currency))
+    }
+
+    /// Returns the currently disputed transactions, sorted by the client ID, the currency and
+    /// the transaction ID. Their amounts add up to the held funds of each account, unless
+    /// the account was opened with held funds.
+    pub fn open_disputes(&self) -> Vec<OpenDispute<M>> {
+        let mut disputes: Vec<OpenDispute<M>> = self
+            .transactions
+            .iter()
+            .filter(|(_, record)| record.state == TxState::Disputed)
+            .map(|(&tx_id, record)| OpenDispute {
+                client_id: record.client_id,
+                currency: record.currency,
+                tx_id,
+                amount: record.disputed_amount(),
+                is_withdrawal: record.is_withdrawal,
+            })
+            .collect();
+        disputes
+            .sort_unstable_by_key(|dispute| (dispute.client_id, dispute.currency, dispute.tx_id));
+        disputes
+    }
+
+    /// Returns the IDs of the deposits and withdrawals kept for the disputes, each with the
+    /// client it belongs to, in no particular order.
+    pub fn transaction_clients(&self) -> impl Iterator<Item = (u32, u16)> + '_ {
+        self.transactions
+            .iter()
+            .map(|(&tx_id, record)| (tx_id, record.client_id))
+    }
+
+    /// Opens an account with the given balances, e.g. carried over from an earlier batch, so the
+    /// later transactions can act on its funds. The balances have to be consistent, and the
+    /// account must not exist yet. The held funds can't be released, since there's no disputed
+    /// transaction behind them.
+    pub fn open_account(&mut self, client: Client<M>) -> Result<(), TransactionError<M>> {
+        let expected = add(client.available, client.held)?;
+        if client.total != expected {
+            return Err(TransactionError::InconsistentBalance {
+                total: client.total,
+                expected,
+            });
+        }
+        if self.clients.contains_key(&(client.id, client.currency)) {
+            return Err(TransactionError::AccountExists(client.id));
+        }
+        let account = self.get_or_create_client(client.id, client.currency)?;
+        *account = Client {
+            peak_held: client.peak_held.max(client.held),
+            ..client
+        };
+        Ok(())
+    }
+
+    /// Checks the balance invariant of every account. It always holds for the accounts built by
+    /// the engine itself, but a state loaded from an external source may be corrupt, in which
+    /// case the offending clients are returned.
+    pub fn validate(&self) -> Result<(), InconsistentLedger> {
+        let mut client_ids: Vec<u16> = self
+            .accounts()
+            .filter(|client| !client.check_invariant())
+            .map(|client| client.id)
+            .collect();
+        client_ids.dedup();
+        if client_ids.is_empty() {
+            Ok(())
+        } else {
+            Err(InconsistentLedger { client_ids })
+        }
+    }
+
+    /// Loads the engine state from a JSON snapshot file, written by [`TransactionEngine::save_state`].
+    /// The configuration isn't part of the snapshot, the loaded engine uses the default one.
+    /// A snapshot with inconsistent balances fails to load, see [`TransactionEngine::validate`].
+    pub fn load_state<P: AsRef<Path>>(path: P) -> io::Result<TransactionEngine<M>>
+    where
+        M: for<'de> Deserialize<'de>,
+    {
+        let file = File::open(path)?;
+        let engine: TransactionEngine<M> = serde_json::from_reader(BufReader::new(file))?;
+        engine
+            .validate()
+            .map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err))?;
+        Ok(engine)
+    }
+
+    /// Saves the engine state, including the client accounts and the disputable transactions,
+    /// to a JSON snapshot file.
+    pub fn save_state<P: AsRef<Path>>(&self, path: P) -> io::Result<()>
+    where
+        M: Serialize,
+    {
+        let mut writer = BufWriter::new(File::create(path)?);
+        serde_json::to_writer(&mut writer, self)?;
+        writer.flush()
+    }
+
+    /// Splits the engine state into a number of engines, each one holding the clients, and
+    /// their transactions, with the client ID modulo the number of shards equal to its index.
+    /// The room reserved for the accounts is split between the shards.
+    pub fn into_shards(self, shards: usize) -> Vec<TransactionEngine<M>> {
+        let capacity = self.clients.capacity().div_ceil(shards);
+        let mut engines: Vec<TransactionEngine<M>> = (0..shards)
+            .map(|_| {
+                let mut engine = TransactionEngine::with_config(self.config);
+                engine.reserve(capacity);
+                engine
+            })
+            .collect();
+        for client in self.clients {
+            engines[usize::from(client.id) % shards]
+                .clients
+                .insert(client);
+        }
+        for tx_id in self.tx_order {
+            if let Some(record) = self.transactions.get(&tx_id) {
+                engines[usize::from(record.client_id) % shards]
+                    .tx_order
+                    .push_back(tx_id);
+            }
+        }
+        for (tx_id, record) in self.transactions {
+            engines[usize::from(record.client_id) % shards]
+                .transactions
+                .insert(tx_id, record);
+        }
+        for (tx_id, pending) in self.pending_disputes {
+            engines[usize::from(pending.client_id) % shards]
+                .pending_disputes
+                .insert(tx_id, pending);
+        }
+        for (tx_id, applied) in self.applied {
+            engines[usize::from(applied.client_id) % shards]
+                .applied
+                .insert(tx_id, applied);
+        }
+        engines
+    }
+
+    /// Forgets the deposits and withdrawals for which the predicate, given the transaction ID
+    /// and the client ID, returns false, so they can no longer be disputed. The transactions
+    /// under dispute are always kept, since they hold funds until settled.
+    pub fn retain_transactions<F: FnMut(u32, u16) -> bool>(&mut self, mut keep: F) {
+        self.transactions.retain(|&tx_id, record| {
+            record.state == TxState::Disputed || keep(tx_id, record.client_id)
+        });
+    }
+
+    /// Merges the state of another engine into this one. The engines are expected to hold
+    /// disjoint sets of clients, e.g. when the transactions were sharded by the client ID.
+    /// The configuration of this engine is kept.
+    pub fn merge(&mut self, other: TransactionEngine<M>) {
+        self.clients.extend(other.clients);
+        for (tx_id, record) in other.transactions {
+            self.transactions.entry(tx_id).or_insert(record);
+        }
+        for (tx_id, pending) in other.pending_disputes {
+            self.pending_disputes.entry(tx_id).or_insert(pending);
+        }
+        for (tx_id, applied) in other.applied {
+            self.applied.entry(tx_id).or_insert(applied);
+        }
+        self.tx_order.extend(other.tx_order);
+        if let Some(window) = self.config.dispute_window {
+            self.evict_transactions(window);
+        }
+    }
+
+    /// Processes a single transaction, while updating the clients, disputable operations, and disputes.
+    /// If the transaction is rejected, the state is left unchanged and the reason is returned,
+    /// except for an account created by a withdrawal, see [`EngineConfig::create_on_withdrawal`].
+    /// In debug builds, panics if the transaction breaks the balance invariant of the client.
+    pub fn process(&mut self, transaction: Transaction<M>) -> Result<(), TransactionError<M>> {
+        self.process_in(transaction, None)
+    }
+
+    /// Processes a single transaction in the given currency, None standing for the default one.
+    /// The currency selects the account of a deposit, withdrawal or unlock, while the disputes, resolves
+    /// and chargebacks always apply to the account of the referenced transaction.
+    /// Otherwise works the same way as [`TransactionEngine::process`].
+    pub fn process_in(
+        &mut self,
+        transaction: Transaction<M>,
+        currency: Option<Currency>,
+    ) -> Result<(), TransactionError<M>> {
+        let client_id = transaction.client_id();
+        let currency = match transaction {
+            Transaction::Deposit(..) | Transaction::Withdrawal(..) | Transaction::Unlock(..) => {
+                currency
+            }
+            _ => find_currency_by_tx_id(&self.transactions, transaction.tx_id()),
+        };
+        let tx_id = transaction.tx_id();
+        let is_disputable = matches!(
+            transaction,
+            Transaction::Deposit(..) | Transaction::Withdrawal(..)
+        );
+        let applied = if self.config.idempotent {
+            AppliedTransaction::of(&transaction, currency)
+        } else {
+            None
+        };
+        if let Some(applied) = &applied {
+            match self.applied.get(&tx_id) {
+                // An exact repeat was already applied, so it's accepted without any change
+                Some(previous) if previous == applied => return Ok(()),
+                Some(_) => return Err(TransactionError::ConflictingRepeat(tx_id)),
+                None => {}
+            }
+        }
+        // A dispute parked for a later transaction changes no balance yet, so it's only counted
+        // and observed once applied, in apply_pending_dispute
+        let parked = matches!(transaction, Transaction::Dispute(..))
+            && self.config.defer_early_disputes
+            && !self.transactions.contains_key(&tx_id);
+        // The transaction is only kept for an observer, sparing the copy otherwise
+        let observed = self.observer.is_some().then(|| transaction.clone());
+        let result = self.apply(transaction, currency);
+        if result.is_ok() {
+            if let Some(applied) = applied {
+                self.applied.insert(tx_id, applied);
+            }
+            if parked {
+                return result;
+            }
+            self.count_transaction(client_id, currency);
+            if let Some(transaction) = observed {
+                self.notify_observer(&transaction, client_id, currency);
+            }
+            if is_disputable {
+                self.apply_pending_dispute(tx_id, currency);
+            }
+        }
+        if let Some(client) = self.clients.get(&(client_id, currency)) {
+            debug_assert!(
+                client.check_invariant(),
+                "Balance invariant broken for client {client_id}: {client:?}"
+            );
+        }
+        result
+    }
+
+    /// Applies the dispute parked for the just processed transaction, if there's one.
+    /// The transaction itself is accepted either way, so a dispute which turns out to be
+    /// invalid, e.g. coming from another client, is dropped.
+    fn apply_pending_dispute(&mut self, tx_id: u32, currency: Option<Currency>) {
+        if let Some(pending) = self.pending_disputes.remove(&tx_id) {
+            let dispute = Transaction::Dispute(pending.client_id, tx_id, pending.amount);
+            let observed = self.observer.is_some().then(|| dispute.clone());
+            if self.apply(dispute, currency).is_ok() {
+                self.count_transaction(pending.client_id, currency);
+                if let Some(dispute) = observed {
+                    self.notify_observer(&dispute, pending.client_id, currency);
+                }
+            }
+        }
+    }
+
+    /// Passes an accepted transaction to the observer, with the account it was applied to.
+    fn notify_observer(
+        &mut self,
+        transaction: &Transaction<M>,
+        client_id: u16,
+        currency: Option<Currency>,
+    ) {
+        if let (Some(observer), Some(client)) =
+            (&mut self.observer, self.clients.get(&(client_id, currency)))
+        {
+            (observer.0)(transaction, client);
+        }
+    }
+
+    /// Counts a transaction applied to the account of the client in the given currency.
+    fn count_transaction(&mut self, client_id: u16, currency: Option<Currency>) {
+        if let Some(client) = find_client_by_id(&mut self.clients, client_id, currency) {
+            client.tx_count += 1;
+        }
+    }
+
+    /// Returns the account of the client in the given currency, creating an empty one if it
+    /// doesn't exist yet, within the configured limit of the accounts.
+    ///
+    /// Only the deposits create the accounts, along with the withdrawals if configured so: a
+    /// withdrawal can't apply to an empty account, while a dispute, resolve or chargeback always
+    /// refers to an earlier deposit or withdrawal, so for an unknown client these are rejected
+    /// without creating anything.
+    fn get_or_create_client(
+        &mut self,
+        client_id: u16,
+        currency: Option<Currency>,
+    ) -> Result<&mut Client<M>, TransactionError<M>> {
+        let key = (client_id, currency);
+        if self.clients.get_mut(&key).is_none() {
+            if let Some(limit) = self.config.max_clients {
+                if self.clients.len() >= limit {
+                    return Err(TransactionError::TooManyClients { limit });
+                }
+            }
+            self.clients.insert(Client::new(client_id, currency));
+        }
+        // The account was remembered by the lookup or the insertion, so it's not hashed again
+        self.clients
+            .get_mut(&key)
+            .ok_or(TransactionError::UnknownClient(client_id))
+    }
+
+    /// Credits a withdrawal fee to the account of the fee client, creating it if needed
+    fn credit_fee(
+        &mut self,
+        fee_client: u16,
+        currency: Option<Currency>,
+        fee: M,
+    ) -> Result<(), TransactionError<M>> {
+        let cl = self.get_or_create_client(fee_client, currency)?;
+        // Both balances are checked before any of them is altered
+        let available = add(cl.available, fee)?;
+        cl.total = add(cl.total, fee)?;
+        cl.available = available;
+        Ok(())
+    }
+
+    /// Registers a deposit or withdrawal for the later disputes, unless its ID is taken already,
+    /// and forgets the oldest transactions beyond the dispute window, if there's one.
+    fn register_transaction(&mut self, tx_id: u32, record: DisputableRecord<M>) {
+        if let Entry::Vacant(entry) = self.transactions.entry(tx_id) {
+            entry.insert(record);
+            if let Some(window) = self.config.dispute_window {
+                self.tx_order.push_back(tx_id);
+                self.evict_transactions(window);
+            }
+        }
+    }
+
+    /// Forgets the oldest disputable transactions, until only the given number of them is left.
+    /// The transactions under dispute are kept, since they hold funds until settled.
+    fn evict_transactions(&mut self, window: usize) {
+        let mut disputed = Vec::new();
+        while self.transactions.len() > window {
+            let Some(tx_id) = self.tx_order.pop_front() else {
+                break;
+            };
+            // The IDs of the transactions no longer kept are skipped
+            match self.transactions.get(&tx_id) {
+                Some(record) if record.state == TxState::Disputed => disputed.push(tx_id),
+                Some(_) => {
+                    self.transactions.remove(&tx_id);
+                }
+                None => {}
+            }
+        }
+        // The disputed transactions keep their place, to be forgotten once settled
+        for tx_id in disputed.into_iter().rev() {
+            self.tx_order.push_front(tx_id);
+        }
+    }
+
+    /// Applies a single transaction to the engine state, using the account in the given currency
+    fn apply(
+        &mut self,
+        transaction: Transaction<M>,
+        currency: Option<Currency>,
+    ) -> Result<(), TransactionError<M>> {
+        match transaction {
+            Transaction::Deposit(client_id, tx_id, amount) => {
+                if let Some(limit) = self.config.max_client_total {
+                    // Checked before the account is created, so a rejected first deposit leaves
+                    // no empty account behind, while a locked account is rejected as such
+                    let account = self.clients.get(&(client_id, currency));
+                    if !account.is_some_and(|cl| cl.locked) {
+                        let total = add(account.map_or_else(M::default, |cl| cl.total), amount)?;
+                        if total > limit {
+                            return Err(TransactionError::LimitExceeded { limit, total });
+                        }
+                    }
+                }
+                let cl = self.get_or_create_client(client_id, currency)?;
+                if cl.locked {
+                    return Err(TransactionError::AccountLocked(cl.id));
+                }
+                // Both balances are checked before any of them is altered
+                let available = add(cl.available, amount)?;
+                cl.total = add(cl.total, amount)?;
+                cl.available = available;
+                // Deposit is always accepted, and registered in the disputable list.
+                // In case of a repeated transaction ID, the first registered operation is kept.
+                let record = DisputableRecord::new(client_id, currency, amount, false);
+                self.register_transaction(tx_id, record);
+            }
+            Transaction::Withdrawal(client_id, tx_id, amount) => {
+                let overdraft = self.config.overdraft;
+                let fee_client = self.config.fee_client;
+                // The fee client would pay the fee to itself
+                let fee = self
+                    .config
+                    .withdrawal_fee
+                    .filter(|_| client_id != fee_client);
+                let charge = match fee {
+                    Some(fee) => add(amount, fee)?,
+                    None => amount,
+                };
+                let cl = if self.config.create_on_withdrawal {
+                    self.get_or_create_client(client_id, currency)?
+                } else {
+                    find_client_by_id(&mut self.clients, client_id, currency)
+                        .ok_or(TransactionError::UnknownClient(client_id))?
+                };
+                if cl.locked {
+                    return Err(TransactionError::AccountLocked(cl.id));
+                }
+                // The available funds can go negative down to the overdraft limit
+                let funds = cl.available.checked_add(overdraft);
+                if funds.is_some_and(|funds| funds < charge) {
+                    return Err(TransactionError::InsufficientFunds {
+                        available: cl.available,
+                        required: charge,
+                    });
+                }
+                let available = sub(cl.available, charge)?;
+                let total = sub(cl.total, charge)?;
+                match fee {
+                    Some(fee) => {
+                        // The fee is credited first, as it may still be rejected, and the
+                        // client's account is only changed once nothing can fail
+                        self.credit_fee(fee_client, currency, fee)?;
+                        let cl = find_client_by_id(&mut self.clients, client_id, currency)
+                            .ok_or(TransactionError::UnknownClient(client_id))?;
+                        cl.total = total;
+                        cl.available = available;
+                    }
+                    None => {
+                        cl.total = total;
+                        cl.available = available;
+                    }
+                }
+                // The fee isn't disputable, the record only holds the withdrawn amount.
+                // Only register the withdrawal in disputable list if it was successful
+                let record = DisputableRecord::new(client_id, currency, amount, true);
+                self.register_transaction(tx_id, record);
+            }
+            Transaction::Dispute(client_id, tx_id, amount)
+                if self.config.defer_early_disputes && !self.transactions.contains_key(&tx_id) =>
+            {
+                // The first dispute is kept, a repeated one would be rejected once applied
+                if self.pending_disputes.contains_key(&tx_id) {
+                    return Err(TransactionError::AlreadyDisputed);
+                }
+                self.pending_disputes
+                    .insert(tx_id, PendingDispute { client_id, amount });
+            }
+            Transaction::Dispute(client_id, tx_id, amount) => {
+                let client = find_client_by_id(&mut self.clients, client_id, currency);
+                match client {
+                    Some(cl) => match self.transactions.get_mut(&tx_id) {
+                        Some(record) => {
+                            // A dispute of another client's transaction must not affect the
+                            // record, so the rightful client can still dispute it
+                            if record.client_id != cl.id {
+                                return Err(TransactionError::ClientMismatch {
+                                    expected: cl.id,
+                                    actual: record.client_id,
+                                });
+                            }
+                            record.state.check_dispute()?;
+                            if record.is_withdrawal && !self.config.allow_withdrawal_disputes {
+                                return Err(TransactionError::WithdrawalDisputeNotAllowed);
+                            }
+                            // Without an amount, the whole transaction is disputed
+                            let amount = amount.unwrap_or(record.amount);
+                            if amount > record.amount {
+                                return Err(TransactionError::AmountTooLarge {
+                                    limit: record.amount,
+                                    requested: amount,
+                                });
+                            }
+                            // A rejected dispute leaves the record as it was, so the
+                            // transaction can still be disputed, e.g. once its account is
+                            // unlocked
+                            apply_dispute(cl, record, amount, self.config.settle_locked_accounts)?;
+                            record.state = TxState::Disputed;
+                            record.disputed = Some(amount);
+                        }
+                        None => {
+                            return Err(TransactionError::UnknownTransaction);
+                        }
+                    },
+                    None => {
+                        return Err(TransactionError::UnknownClient(client_id));
+                    }
+                }
+            }
+            Transaction::Resolve(client_id, tx_id) => {
+                let client = find_client_by_id(&mut self.clients, client_id, currency);
+                match client {
+                    Some(cl) => match self.transactions.get_mut(&tx_id) {
+                        Some(record) => {
+                            record.state.check_settle()?;
+                            apply_resolve(cl, record, self.config.settle_locked_accounts)?;
+                            // Once the dispute is resolved, the operation can no longer be "finalized" again
+                            record.state = TxState::Resolved;
+                        }
+                        None => {
+                            return Err(TransactionError::UnknownTransaction);
+                        }
+                    },
+                    None => {
+                        return Err(TransactionError::UnknownClient(client_id));
+                    }
+                }
+            }
+            Transaction::Chargeback(client_id, tx_id, amount) => {
+                let client = find_client_by_id(&mut self.clients, client_id, currency);
+                match client {
+                    Some(cl) => match self.transactions.get_mut(&tx_id) {
+                        Some(record) => {
+                            record.state.check_settle()?;
+                            // Without an amount, the whole disputed amount is charged back
+                            let amount = amount.unwrap_or(record.disputed_amount());
+                            if amount > record.disputed_amount() {
+                                return Err(TransactionError::AmountTooLarge {
+                                    limit: record.disputed_amount(),
+                                    requested: amount,
+                                });
+                            }
+                            apply_chargeback(
+                                cl,
+                                record,
+                                amount,
+                                self.config.settle_locked_accounts,
+                            )?;
+                            // Once the dispute is charged back, the operation can no longer be "finalized" again
+                            record.state = TxState::ChargedBack;
+                        }
+                        None => {
+                            return Err(TransactionError::UnknownTransaction);
+                        }
+                    },
+                    None => {
+                        return Err(TransactionError::UnknownClient(client_id));
+                    }
+                }
+            }
+            Transaction::Unlock(client_id, _) => {
+                if !self.config.allow_unlock {
+                    return Err(TransactionError::UnlockNotAllowed);
+                }
+                let cl = find_client_by_id(&mut self.clients, client_id, currency)
+                    .ok_or(TransactionError::UnknownClient(client_id))?;
+                if !cl.locked {
+                    return Err(TransactionError::AccountNotLocked(client_id));
+                }
+                // The charged back transactions stay final, only the new ones are accepted again
+                cl.locked = false;
+            }
+        }
+        Ok(())
+    }
+}
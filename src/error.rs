@@ -0,0 +1,202 @@
+use crate::{Amount, Money};
+use std::error::Error;
+use std::fmt;
+use std::io;
+
+/// Reasons for the engine to reject a transaction
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum TransactionError<M = Amount> {
+    /// The client account is locked, no further changes are accepted
+    AccountLocked(u16),
+    /// The client doesn't have enough available funds for the operation
+    InsufficientFunds { available: M, required: M },
+    /// There's no account for the client referenced by the transaction
+    UnknownClient(u16),
+    /// The referenced transaction doesn't exist, or can't be disputed or settled
+    UnknownTransaction,
+    /// The referenced transaction belongs to a different client
+    ClientMismatch { expected: u16, actual: u16 },
+    /// The referenced transaction is already under dispute
+    AlreadyDisputed,
+    /// The referenced transaction is not under dispute, so it can't be settled
+    NotDisputed,
+    /// The dispute of the referenced transaction was already resolved
+    AlreadyResolved,
+    /// The referenced transaction was already charged back
+    AlreadyChargedBack,
+    /// The amount of a partial dispute or chargeback exceeds the amount it applies to
+    AmountTooLarge { limit: M, requested: M },
+    /// The held funds of the client don't cover the disputed amount, the engine state is inconsistent
+    InconsistentHeld { held: M, required: M },
+    /// The referenced transaction is a withdrawal, while only the deposits can be disputed
+    WithdrawalDisputeNotAllowed,
+    /// The operation would overflow one of the client balances
+    Overflow,
+    /// The transaction would create a new client account beyond the configured limit
+    TooManyClients { limit: usize },
+    /// The opened account's total funds are not the sum of its available and held funds
+    InconsistentBalance { total: M, expected: M },
+    /// The opened account already exists in the engine
+    AccountExists(u16),
+    /// The unlock operations are not enabled in the engine configuration
+    UnlockNotAllowed,
+    /// The account to unlock is not locked
+    AccountNotLocked(u16),
+    /// In the idempotent mode, the transaction ID was already applied with different details
+    ConflictingRepeat(u32),
+    /// The deposit would raise the client's total funds above the configured limit
+    LimitExceeded { limit: M, total: M },
+}
+
+impl<M> TransactionError<M> {
+    /// Returns a short machine-readable code of the rejection reason
+    pub fn code(&self) -> &'static str {
+        match self {
+            TransactionError::AccountLocked(_) => "account_locked",
+            TransactionError::InsufficientFunds { .. } => "insufficient_funds",
+            TransactionError::UnknownClient(_) => "unknown_client",
+            TransactionError::UnknownTransaction => "unknown_transaction",
+            TransactionError::ClientMismatch { .. } => "client_mismatch",
+            TransactionError::AlreadyDisputed => "already_disputed",
+            TransactionError::NotDisputed => "not_disputed",
+            TransactionError::AlreadyResolved => "already_resolved",
+            TransactionError::AlreadyChargedBack => "already_charged_back",
+            TransactionError::AmountTooLarge { .. } => "amount_too_large",
+            TransactionError::InconsistentHeld { .. } => "inconsistent_held",
+            TransactionError::WithdrawalDisputeNotAllowed => "withdrawal_dispute_not_allowed",
+            TransactionError::Overflow => "overflow",
+            TransactionError::TooManyClients { .. } => "too_many_clients",
+            TransactionError::InconsistentBalance { .. } => "inconsistent_balance",
+            TransactionError::AccountExists(_) => "account_exists",
+            TransactionError::UnlockNotAllowed => "unlock_not_allowed",
+            TransactionError::AccountNotLocked(_) => "account_not_locked",
+            TransactionError::ConflictingRepeat(_) => "conflicting_repeat",
+            TransactionError::LimitExceeded { .. } => "limit_exceeded",
+        }
+    }
+}
+
+impl<M: Money> fmt::Display for TransactionError<M> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            TransactionError::AccountLocked(client_id) => {
+                write!(f, "can't be applied to a locked account {client_id}")
+            }
+            TransactionError::InsufficientFunds {
+                available,
+                required,
+            } => write!(f, "doesn't have enough funds ({available} < {required})"),
+            TransactionError::UnknownClient(client_id) => write!(f, "unknown client {client_id}"),
+            TransactionError::UnknownTransaction => write!(f, "transaction unknown or invalid"),
+            TransactionError::ClientMismatch { expected, actual } => {
+                write!(f, "client mismatch exp:{expected} act:{actual}")
+            }
+            TransactionError::AlreadyDisputed => write!(f, "transaction is already disputed"),
+            TransactionError::NotDisputed => write!(f, "transaction is not disputed"),
+            TransactionError::AlreadyResolved => {
+                write!(f, "transaction dispute is already resolved")
+            }
+            TransactionError::AlreadyChargedBack => {
+                write!(f, "transaction is already charged back")
+            }
+            TransactionError::AmountTooLarge { limit, requested } => {
+                write!(f, "amount {requested} exceeds the disputed amount {limit}")
+            }
+            TransactionError::InconsistentHeld { held, required } => {
+                write!(
+                    f,
+                    "held funds don't cover the disputed amount ({held} < {required})"
+                )
+            }
+            TransactionError::WithdrawalDisputeNotAllowed => {
+                write!(f, "withdrawals can't be disputed")
+            }
+            TransactionError::Overflow => write!(f, "would overflow the account balance"),
+            TransactionError::TooManyClients { limit } => {
+                write!(f, "would exceed the limit of {limit} client accounts")
+            }
+            TransactionError::InconsistentBalance { total, expected } => {
+                write!(
+                    f,
+                    "total {total} doesn't match available and held {expected}"
+                )
+            }
+            TransactionError::AccountExists(client_id) => {
+                write!(f, "account of client {client_id} already exists")
+            }
+            TransactionError::UnlockNotAllowed => write!(f, "accounts can't be unlocked"),
+            TransactionError::AccountNotLocked(client_id) => {
+                write!(f, "account of client {client_id} is not locked")
+            }
+            TransactionError::ConflictingRepeat(tx_id) => {
+                write!(
+                    f,
+                    "transaction {tx_id} was already applied with different details"
+                )
+            }
+            TransactionError::LimitExceeded { limit, total } => {
+                write!(f, "total funds {total} would exceed the limit of {limit}")
+            }
+        }
+    }
+}
+
+impl<M: Money> Error for TransactionError<M> {}
+
+/// Client accounts with the total funds not matching the sum of their available and held
+/// funds, found in a state loaded from an external source
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct InconsistentLedger {
+    /// IDs of the offending clients, sorted and listed once even with several currencies
+    pub client_ids: Vec<u16>,
+}
+
+impl fmt::Display for InconsistentLedger {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let ids: Vec<String> = self.client_ids.iter().map(u16::to_string).collect();
+        write!(f, "inconsistent balances of clients {}", ids.join(", "))
+    }
+}
+
+impl Error for InconsistentLedger {}
+
+/// Reasons for [`run_from_path`](crate::run_from_path) to stop processing a transaction file
+#[derive(Debug)]
+pub enum EngineError {
+    /// The file can't be opened or read
+    Io(io::Error),
+    /// A record of the file can't be parsed, e.g. has a non-numeric client ID
+    Malformed(csv::Error),
+}
+
+impl EngineError {
+    /// Separates the read failures from the malformed records among the CSV errors
+    pub(crate) fn from_csv(err: csv::Error) -> EngineError {
+        if err.is_io_error() {
+            match err.into_kind() {
+                csv::ErrorKind::Io(err) => EngineError::Io(err),
+                _ => unreachable!("checked to be an I/O error"),
+            }
+        } else {
+            EngineError::Malformed(err)
+        }
+    }
+}
+
+impl fmt::Display for EngineError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            EngineError::Io(err) => write!(f, "can't read the input: {err}"),
+            EngineError::Malformed(err) => write!(f, "malformed record: {err}"),
+        }
+    }
+}
+
+impl Error for EngineError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            EngineError::Io(err) => Some(err),
+            EngineError::Malformed(err) => Some(err),
+        }
+    }
+}
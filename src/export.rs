@@ -0,0 +1,86 @@
+use crate::{Amount, Client, Currency};
+use serde::{Deserialize, Serialize};
+use std::io::{self, Read, Write};
+
+/// Client record in the bincode format. All the fields are always written, since the format
+/// isn't self-describing, so none of them can be skipped as in the text formats.
+#[derive(Serialize, Deserialize)]
+struct BinaryClient {
+    id: u16,
+    currency: Option<Currency>,
+    available: Amount,
+    held: Amount,
+    total: Amount,
+    locked: bool,
+    tx_count: u64,
+    peak_held: Amount,
+}
+
+impl From<&Client> for BinaryClient {
+    fn from(client: &Client) -> BinaryClient {
+        BinaryClient {
+            id: client.id,
+            currency: client.currency,
+            available: client.available,
+            held: client.held,
+            total: client.total,
+            locked: client.locked,
+            tx_count: client.tx_count,
+            peak_held: client.peak_held,
+        }
+    }
+}
+
+impl From<BinaryClient> for Client {
+    fn from(client: BinaryClient) -> Client {
+        Client {
+            id: client.id,
+            currency: client.currency,
+            available: client.available,
+            held: client.held,
+            total: client.total,
+            locked: client.locked,
+            tx_count: client.tx_count,
+            peak_held: client.peak_held,
+        }
+    }
+}
+
+/// Converts a bincode error to an I/O one, keeping the underlying I/O error if there's one.
+fn into_io_error(err: bincode::ErrorKind) -> io::Error {
+    match err {
+        bincode::ErrorKind::Io(err) => err,
+        other => io::Error::new(io::ErrorKind::InvalidData, other),
+    }
+}
+
+/// Writes the client records as a single list in the compact bincode format, with the money
+/// amounts kept as exact fixed-point values, to be read back by [`read_accounts_bincode`]:
+///
+/// ```
+/// use transact::{read_accounts_bincode, write_accounts_bincode, Transaction, TransactionEngine};
+///
+/// let mut engine = TransactionEngine::new();
+/// engine.process(Transaction::Deposit(1, 1, "2.5".parse().unwrap())).unwrap();
+/// let mut buffer = Vec::new();
+/// write_accounts_bincode(&mut buffer, engine.accounts()).unwrap();
+/// let clients = read_accounts_bincode(buffer.as_slice()).unwrap();
+/// assert_eq!(clients, [engine.get_account(1).unwrap().clone()]);
+/// ```
+pub fn write_accounts_bincode<'a, W, I>(writer: W, clients: I) -> io::Result<()>
+where
+    W: Write,
+    I: IntoIterator<Item = &'a Client>,
+{
+    let clients: Vec<BinaryClient> = clients.into_iter().map(BinaryClient::from).collect();
+    bincode::serialize_into(writer, &clients).map_err(|err| into_io_error(*err))
+}
+
+/// Reads a list of client records written by [`write_accounts_bincode`]. Each call reads one
+/// list, so a stream of several lists, e.g. written in the follow mode of the binary, can be
+/// read by repeated calls.
+pub fn read_accounts_bincode<R: Read>(reader: R) -> io::Result<Vec<Client>> {
+    let clients: Vec<BinaryClient> =
+        bincode::deserialize_from(reader).map_err(|err| into_io_error(*err))?;
+    Ok(clients.into_iter().map(Client::from).collect())
+}
@@ -0,0 +1,417 @@
+use crate::{Amount, Currency, Timestamp, Transaction, AMOUNT_DECIMALS};
+use serde::de::{self, Deserializer, Visitor};
+use serde::Deserialize;
+use std::error::Error;
+use std::fmt;
+use std::marker::PhantomData;
+
+/// This struct holds the CSV line input, deserialized from the file
+#[derive(Deserialize, Debug, Clone)]
+pub struct OperationInput {
+    #[serde(rename = "type")]
+    pub op_type: String,
+    #[serde(deserialize_with = "deserialize_client_id")]
+    pub client: u16,
+    #[serde(deserialize_with = "deserialize_tx_id")]
+    pub tx: u32,
+    /// Amount as written in the input; numbers are accepted as well, e.g. from JSON inputs
+    #[serde(default, deserialize_with = "deserialize_amount_text")]
+    pub amount: Option<String>,
+    /// Optional currency code, the default currency is used if the column is missing or empty
+    #[serde(default)]
+    pub currency: Option<String>,
+    /// Optional ISO-8601 time of the transaction, only used for ordering the transactions
+    #[serde(default)]
+    pub timestamp: Option<String>,
+}
+
+/// Deserializes the client ID, describing a number beyond the range of the IDs with
+/// [`InputError::ClientIdOutOfRange`].
+fn deserialize_client_id<'de, D: Deserializer<'de>>(deserializer: D) -> Result<u16, D::Error> {
+    deserializer.deserialize_newtype_struct(
+        RAW_JSON_VALUE,
+        IdVisitor {
+            name: "a client ID",
+            out_of_range: InputError::ClientIdOutOfRange,
+            _id: PhantomData,
+        },
+    )
+}
+
+/// Deserializes the transaction ID, describing a number beyond the range of the IDs with
+/// [`InputError::TxIdOutOfRange`].
+fn deserialize_tx_id<'de, D: Deserializer<'de>>(deserializer: D) -> Result<u32, D::Error> {
+    deserializer.deserialize_newtype_struct(
+        RAW_JSON_VALUE,
+        IdVisitor {
+            name: "a transaction ID",
+            out_of_range: InputError::TxIdOutOfRange,
+            _id: PhantomData,
+        },
+    )
+}
+
+/// Visitor of an unsigned integer ID, read from its decimal digits as written, like the
+/// amounts: the raw text of a JSON number, or the field of the other formats, such as CSV.
+/// This way a number of any size is told to be out of range, rather than being taken for a
+/// floating point number by the CSV reader.
+struct IdVisitor<T> {
+    name: &'static str,
+    out_of_range: fn(String) -> InputError,
+    _id: PhantomData<T>,
+}
+
+impl<T: TryFrom<u64>> IdVisitor<T> {
+    /// Converts the digits, with an optional minus sign, to the ID, or returns None if the
+    /// text is not an integer
+    fn parse<E: de::Error>(&self, text: &str) -> Option<Result<T, E>> {
+        let digits = text.strip_prefix('-').unwrap_or(text);
+        if digits.is_empty() || !digits.bytes().all(|ch| ch.is_ascii_digit()) {
+            return None;
+        }
+        let out_of_range = || E::custom((self.out_of_range)(text.to_string()));
+        let id = match text.parse::<u64>() {
+            Ok(value) => T::try_from(value).map_err(|_| out_of_range()),
+            Err(_) => Err(out_of_range()),
+        };
+        Some(id)
+    }
+}
+
+impl<'de, T: TryFrom<u64>> Visitor<'de> for IdVisitor<T> {
+    type Value = T;
+
+    fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(self.name)
+    }
+
+    fn visit_newtype_struct<D: Deserializer<'de>>(self, d: D) -> Result<Self::Value, D::Error> {
+        d.deserialize_str(self)
+    }
+
+    fn visit_str<E: de::Error>(self, v: &str) -> Result<Self::Value, E> {
+        self.parse(v)
+            .unwrap_or_else(|| Err(E::invalid_value(de::Unexpected::Str(v), &self)))
+    }
+
+    fn visit_map<A: de::MapAccess<'de>>(self, mut map: A) -> Result<Self::Value, A::Error> {
+        // The raw JSON value comes as a single entry, keyed by the name it was requested by
+        let Some((_, raw)) = map.next_entry::<String, String>()? else {
+            return Err(de::Error::invalid_length(0, &self));
+        };
+        if let Some(id) = self.parse(&raw) {
+            return id;
+        }
+        let unexpected = match raw.as_bytes().first() {
+            // A JSON string is the wrong type, even if it holds the digits of an ID
+            Some(b'"') => serde_json::from_str::<String>(&raw)
+                .map(|text| format!("string {text:?}"))
+                .unwrap_or(raw),
+            Some(b'-' | b'0'..=b'9') => format!("number {raw}"),
+            _ => raw,
+        };
+        Err(de::Error::invalid_type(
+            de::Unexpected::Other(&unexpected),
+            &self,
+        ))
+    }
+}
+
+/// Name under which serde_json hands out the raw text of a JSON value, see
+/// `serde_json::value::RawValue`
+const RAW_JSON_VALUE: &str = "$serde_json::private::RawValue";
+
+/// Deserializes the optional amount text exactly as written, accepting the JSON numbers as
+/// well, so the amount never goes through a floating point number, which would lose its digits.
+///
+/// The amount is requested as the raw JSON value, which serde_json provides as its text, while
+/// the other formats, such as CSV, hand the field to the newtype visitor, which reads it as a
+/// string.
+fn deserialize_amount_text<'de, D: Deserializer<'de>>(
+    deserializer: D,
+) -> Result<Option<String>, D::Error> {
+    struct AmountTextVisitor;
+
+    impl<'de> Visitor<'de> for AmountTextVisitor {
+        type Value = Option<String>;
+
+        fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+            write!(f, "an amount, as a string or a number")
+        }
+
+        fn visit_none<E: de::Error>(self) -> Result<Self::Value, E> {
+            Ok(None)
+        }
+
+        fn visit_unit<E: de::Error>(self) -> Result<Self::Value, E> {
+            Ok(None)
+        }
+
+        fn visit_some<D: Deserializer<'de>>(self, d: D) -> Result<Self::Value, D::Error> {
+            d.deserialize_newtype_struct(RAW_JSON_VALUE, self)
+        }
+
+        fn visit_newtype_struct<D: Deserializer<'de>>(self, d: D) -> Result<Self::Value, D::Error> {
+            d.deserialize_str(self)
+        }
+
+        fn visit_str<E: de::Error>(self, v: &str) -> Result<Self::Value, E> {
+            Ok(Some(v.to_string()))
+        }
+
+        fn visit_map<A: de::MapAccess<'de>>(self, mut map: A) -> Result<Self::Value, A::Error> {
+            // The raw JSON value comes as a single entry, keyed by the name it was requested by
+            let Some((_, raw)) = map.next_entry::<String, String>()? else {
+                return Err(de::Error::invalid_length(0, &self));
+            };
+            match raw.as_bytes().first() {
+                Some(b'"') => serde_json::from_str(&raw)
+                    .map(Some)
+                    .map_err(de::Error::custom),
+                Some(b'-' | b'0'..=b'9') => Ok(Some(raw)),
+                Some(b'n') if raw == "null" => Ok(None),
+                _ => Err(de::Error::invalid_value(de::Unexpected::Str(&raw), &self)),
+            }
+        }
+    }
+
+    deserializer.deserialize_option(AmountTextVisitor)
+}
+
+/// Options of the conversion of the input records to transactions
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct InputConfig {
+    strict_precision: bool,
+    locale_amounts: bool,
+}
+
+impl InputConfig {
+    /// Creates the default configuration
+    pub fn new() -> InputConfig {
+        InputConfig::default()
+    }
+
+    /// Sets whether the amounts with more than four decimal places are rejected, instead of
+    /// being truncated. Disabled by default.
+    pub fn strict_precision(mut self, strict: bool) -> InputConfig {
+        self.strict_precision = strict;
+        self
+    }
+
+    /// Sets whether the amounts may be wrapped in quotes, and have their integer digits grouped
+    /// by commas, e.g. `"1,000.50"`. Disabled by default.
+    pub fn locale_amounts(mut self, locale: bool) -> InputConfig {
+        self.locale_amounts = locale;
+        self
+    }
+}
+
+/// Reasons for an input record not to describe a valid transaction
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum InputError {
+    /// The operation type is not recognized
+    UnknownOperation(String),
+    /// The deposit or withdrawal doesn't have an amount
+    MissingAmount,
+    /// The amount can't be parsed as a decimal number
+    InvalidAmount(String),
+    /// The deposit or withdrawal amount is below zero
+    NegativeAmount(Amount),
+    /// The amount has more decimal places than kept by the engine, in the strict precision mode
+    TooPrecise(String),
+    /// The currency is not a three-letter code
+    InvalidCurrency(String),
+    /// The timestamp is not an ISO-8601 date or date and time
+    InvalidTimestamp(String),
+    /// The client ID doesn't fit the range of the IDs, from 0 to 65535. Reported as the reason
+    /// of a malformed record, since the record can't be read.
+    ClientIdOutOfRange(String),
+    /// The transaction ID doesn't fit the range of the IDs, from 0 to 4294967295. Reported as
+    /// the reason of a malformed record, since the record can't be read.
+    TxIdOutOfRange(String),
+}
+
+impl InputError {
+    /// Returns a short machine-readable code of the rejection reason
+    pub fn code(&self) -> &'static str {
+        match self {
+            InputError::UnknownOperation(_) => "unknown_operation",
+            InputError::MissingAmount => "missing_amount",
+            InputError::InvalidAmount(_) => "invalid_amount",
+            InputError::NegativeAmount(_) => "negative_amount",
+            InputError::TooPrecise(_) => "too_precise",
+            InputError::InvalidCurrency(_) => "invalid_currency",
+            InputError::InvalidTimestamp(_) => "invalid_timestamp",
+            InputError::ClientIdOutOfRange(_) => "client_id_out_of_range",
+            InputError::TxIdOutOfRange(_) => "tx_id_out_of_range",
+        }
+    }
+}
+
+impl fmt::Display for InputError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            InputError::UnknownOperation(op) => {
+                write!(f, "unknown operation {op}")?;
+                // The invisible or look-alike characters are only told apart by their bytes
+                if !op.bytes().all(|byte| byte.is_ascii_graphic()) {
+                    write!(f, " (bytes")?;
+                    for byte in op.bytes() {
+                        write!(f, " {byte:02x}")?;
+                    }
+                    write!(f, ")")?;
+                }
+                Ok(())
+            }
+            InputError::MissingAmount => write!(f, "missing amount"),
+            InputError::InvalidAmount(text) => write!(f, "invalid amount '{text}'"),
+            InputError::NegativeAmount(amount) => write!(f, "negative amount {amount}"),
+            InputError::TooPrecise(text) => {
+                write!(
+                    f,
+                    "amount '{text}' has more than {AMOUNT_DECIMALS} decimal places"
+                )
+            }
+            InputError::InvalidCurrency(text) => write!(f, "invalid currency '{text}'"),
+            InputError::InvalidTimestamp(text) => write!(f, "invalid timestamp '{text}'"),
+            InputError::ClientIdOutOfRange(id) => {
+                write!(
+                    f,
+                    "client ID {id} is out of range, the maximum is {}",
+                    u16::MAX
+                )
+            }
+            InputError::TxIdOutOfRange(id) => {
+                write!(
+                    f,
+                    "transaction ID {id} is out of range, the maximum is {}",
+                    u32::MAX
+                )
+            }
+        }
+    }
+}
+
+impl Error for InputError {}
+
+/// Strips the quotes around the amount, and the commas grouping its integer digits by three,
+/// e.g. `"1,000.50"` becomes `1000.50`. Returns None if the commas don't group the digits.
+fn strip_grouping(text: &str) -> Option<String> {
+    let text = text.trim();
+    let text = ['"', '\'']
+        .into_iter()
+        .find_map(|quote| text.strip_prefix(quote)?.strip_suffix(quote))
+        .unwrap_or(text)
+        .trim();
+    let (integer, fraction) = text.split_once('.').unwrap_or((text, ""));
+    if integer.contains(',') {
+        let mut groups = integer.trim_start_matches(['-', '+']).split(',');
+        let first = groups.next()?;
+        if first.is_empty() || first.len() > 3 || groups.any(|group| group.len() != 3) {
+            return None;
+        }
+    }
+    if fraction.contains(',') {
+        return None;
+    }
+    Some(text.replace(',', ""))
+}
+
+/// Parses the amount of a deposit or withdrawal row.
+/// Returns an error if the amount is missing, malformed (including NaN or infinite values), or negative,
+/// or if it has more than four decimal places in the strict precision mode.
+fn parse_amount(amount: Option<&str>, config: &InputConfig) -> Result<Amount, InputError> {
+    let amount: Amount = match amount {
+        Some(original) => {
+            let stripped;
+            let text = if config.locale_amounts {
+                stripped = strip_grouping(original)
+                    .ok_or_else(|| InputError::InvalidAmount(original.to_string()))?;
+                stripped.as_str()
+            } else {
+                original
+            };
+            let decimals = text.split_once('.').map_or(0, |(_, frac)| frac.len());
+            if config.strict_precision && decimals > AMOUNT_DECIMALS {
+                return Err(InputError::TooPrecise(original.to_string()));
+            }
+            text.parse()
+                .map_err(|_| InputError::InvalidAmount(original.to_string()))?
+        }
+        None => return Err(InputError::MissingAmount),
+    };
+    if amount.is_negative() {
+        return Err(InputError::NegativeAmount(amount));
+    }
+    Ok(amount)
+}
+
+/// Checks whether the character is whitespace, or invisible like the zero-width space or the
+/// byte order mark, which tend to stick to the values copied from documents and spreadsheets
+fn is_blank_char(ch: char) -> bool {
+    ch.is_whitespace() || matches!(ch, '\u{200b}'..='\u{200d}' | '\u{2060}' | '\u{feff}')
+}
+
+impl OperationInput {
+    /// Returns the operation type, trimmed of the whitespace and the invisible characters
+    /// around it.
+    pub fn operation_type(&self) -> &str {
+        self.op_type.trim_matches(is_blank_char)
+    }
+
+    /// Converts the input record to the corresponding transaction, with the default options.
+    /// The operation type is matched case-insensitively.
+    pub fn to_transaction(&self) -> Result<Transaction, InputError> {
+        self.to_transaction_with(&InputConfig::default())
+    }
+
+    /// Converts the input record to the corresponding transaction, with the given options.
+    /// The operation type is matched case-insensitively, ignoring the whitespace and the
+    /// invisible characters around it.
+    pub fn to_transaction_with(&self, config: &InputConfig) -> Result<Transaction, InputError> {
+        let amount = || parse_amount(self.amount.as_deref(), config);
+        // The amount of a dispute or chargeback is optional, limiting it to a portion. A blank
+        // or zero amount, as written by some exporters, stands for the whole transaction.
+        let partial_amount = || {
+            let text = self.amount.as_deref().map(str::trim);
+            let partial = text.filter(|text| !text.is_empty());
+            let amount = partial
+                .map(|text| parse_amount(Some(text), config))
+                .transpose()?;
+            Ok(amount.filter(|amount| *amount != Amount::default()))
+        };
+        // Need to convert from string representation to an Enum
+        let transaction = match self.operation_type().to_ascii_lowercase().as_str() {
+            "deposit" => Transaction::Deposit(self.client, self.tx, amount()?),
+            "withdrawal" => Transaction::Withdrawal(self.client, self.tx, amount()?),
+            "dispute" => Transaction::Dispute(self.client, self.tx, partial_amount()?),
+            "resolve" => Transaction::Resolve(self.client, self.tx),
+            "chargeback" => Transaction::Chargeback(self.client, self.tx, partial_amount()?),
+            "unlock" => Transaction::Unlock(self.client, self.tx),
+            _ => return Err(InputError::UnknownOperation(self.op_type.clone())),
+        };
+        Ok(transaction)
+    }
+
+    /// Returns the currency of the record, or None for the default currency.
+    pub fn currency(&self) -> Result<Option<Currency>, InputError> {
+        self.currency
+            .as_deref()
+            .map(|text| {
+                text.parse()
+                    .map_err(|_| InputError::InvalidCurrency(text.to_string()))
+            })
+            .transpose()
+    }
+
+    /// Returns the timestamp of the record, or None if it doesn't have one.
+    pub fn timestamp(&self) -> Result<Option<Timestamp>, InputError> {
+        self.timestamp
+            .as_deref()
+            .map(|text| {
+                text.parse()
+                    .map_err(|_| InputError::InvalidTimestamp(text.to_string()))
+            })
+            .transpose()
+    }
+}
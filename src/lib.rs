@@ -0,0 +1,37 @@
+//! Account transaction processing engine.
+//!
+//! The [`TransactionEngine`] consumes a stream of [`Transaction`]s and maintains
+//! the resulting [`Client`] account balances. Its behavior can be adjusted with an
+//! [`EngineConfig`]. The balances are kept in the fixed-point [`Amount`] by default, while
+//! any other [`Money`] type can be used instead.
+
+mod accounts;
+mod amount;
+mod client;
+mod config;
+mod currency;
+mod engine;
+mod error;
+mod export;
+mod input;
+mod money;
+mod timestamp;
+mod transaction;
+
+#[cfg(feature = "async")]
+mod stream;
+
+pub use amount::{Amount, ParseAmountError, RoundingMode, AMOUNT_DECIMALS, AMOUNT_SCALE};
+pub use client::Client;
+pub use config::EngineConfig;
+pub use currency::{Currency, ParseCurrencyError};
+pub use engine::{run_from_bytes, run_from_path, run_transactions, OpenDispute, TransactionEngine};
+pub use error::{EngineError, InconsistentLedger, TransactionError};
+pub use export::{read_accounts_bincode, write_accounts_bincode};
+pub use input::{InputConfig, InputError, OperationInput};
+pub use money::Money;
+pub use timestamp::{ParseTimestampError, Timestamp};
+pub use transaction::{Transaction, OPERATION_NAMES};
+
+#[cfg(feature = "async")]
+pub use stream::process_stream;
@@ -1,42 +1,62 @@
-use csv::{ReaderBuilder, Trim};
+use csv::{ReaderBuilder, StringRecord, Trim};
+use flate2::read::GzDecoder;
+use log::{error, warn, LevelFilter};
+use serde::ser::SerializeMap;
 use serde::ser::StdError;
-use serde::{Deserialize, Serialize};
+use serde::{Serialize, Serializer};
+use std::collections::{BTreeMap, HashMap, HashSet};
 use std::env;
 use std::error::Error;
-use std::ffi::OsString;
+use std::ffi::{OsStr, OsString};
 use std::fmt;
-use std::{io, process};
+use std::fs::File;
+use std::io::{BufRead, BufReader, Write};
+use std::ops::RangeInclusive;
+use std::path::Path;
+use std::str::FromStr;
+use std::sync::mpsc;
+use std::time::{Duration, Instant};
+use std::{io, mem, process, slice, thread};
+use transact::{
+    write_accounts_bincode, Amount, Client, Currency, EngineConfig, InconsistentLedger,
+    InputConfig, InputError, OperationInput, RoundingMode, Transaction, TransactionEngine,
+    TransactionError, AMOUNT_DECIMALS, OPERATION_NAMES,
+};
 
-/// Client data structure with support for serialized output
-#[derive(Serialize, Debug)]
-struct Client {
-    #[serde(rename = "client")]
-    id: u16,
-    available: f32,
-    held: f32,
-    total: f32,
-    locked: bool,
-}
-
-/// Type describing the possible transactions supported by the engine
-#[derive(Debug, Clone)]
-enum Transaction {
-    Deposit(u16, u32, f32),
-    Withdrawal(u16, u32, f32),
-    Dispute(u16, u32),
-    Resolve(u16, u32),
-    Chargeback(u16, u32),
-}
+/// Number of transactions sent to a shard thread at once
+const SHARD_BATCH_SIZE: usize = 1024;
+/// Number of transaction batches that can be queued for a shard thread
+const SHARD_QUEUE_LEN: usize = 16;
+/// Exit code of the program when any transaction was rejected
+const REJECTED_EXIT_CODE: i32 = 2;
+/// Type of the sentinel record requesting the current account state in the follow mode
+const SNAPSHOT_OP: &str = "snapshot";
+/// Number of rows read between the checks of the progress counter
+const PROGRESS_ROWS: u64 = 100_000;
+/// Shortest delay between the updates of the progress counter
+const PROGRESS_DELAY: Duration = Duration::from_millis(500);
 
 #[derive(Debug)]
 struct ParserError {
     messsage: String,
+    /// Whether the error comes from a single malformed record, rather than the whole input
+    malformed: bool,
 }
 
 impl ParserError {
     fn new(msg: &String) -> ParserError {
         ParserError {
             messsage: msg.to_string(),
+            malformed: false,
+        }
+    }
+
+    /// Creates an error of a single record which can't be deserialized, after which the
+    /// input can still be read further.
+    fn malformed(msg: &String) -> ParserError {
+        ParserError {
+            malformed: true,
+            ..ParserError::new(msg)
         }
     }
 }
@@ -65,441 +85,2214 @@ impl From<csv::Error> for ParserError {
     }
 }
 
-/// This struct holds the CSV line input, deserialized from the file
-#[derive(Deserialize, Debug)]
-struct OperationInput {
+/// Command line options of the program
+#[derive(Debug)]
+struct Args {
+    /// Input CSV files, processed in the given order; the stdin is used if none is provided
+    inputs: Vec<OsString>,
+    /// Output CSV file, the stdout is used if not provided
+    output: Option<OsString>,
+    /// Exits with a distinct code if any transaction was rejected
+    fail_on_reject: bool,
+    /// Only checks the input, without processing it or writing the accounts
+    validate: bool,
+    /// Keeps reading the named pipe inputs, writing the accounts on the snapshot records
+    follow: bool,
+    /// Processes the transactions in the order of their timestamps, rather than the input order
+    sort_by_time: bool,
+    /// Refuses the CSV inputs with unknown columns or rows of a different length than the header
+    strict_schema: bool,
+    /// Only writes the capabilities of the binary, without reading any input
+    capabilities: bool,
+    /// Takes the inputs as the account lists of separate runs, and writes them merged
+    merge: bool,
+    /// Merges the accounts of a client even if their locked states differ
+    force: bool,
+    /// Writes the summary of the processing to stderr once the accounts are written
+    summary: bool,
+    /// Writes the processing throughput statistics to stderr once the accounts are written
+    stats: bool,
+    /// Skips the records which can't be deserialized, instead of stopping the processing
+    skip_bad_rows: bool,
+    /// Shows a counter of the rows read on stderr while processing
+    progress: bool,
+    /// Overdraft limit of every client account
+    overdraft: Amount,
+    /// Limit of the total funds of every client account, unlimited if not provided
+    max_client_total: Option<Amount>,
+    /// Fee charged on every successful withdrawal, none if not provided
+    withdrawal_fee: Option<Amount>,
+    /// Client credited with the withdrawal fees, 0 if not provided
+    fee_client: Option<u16>,
+    /// Maximum number of client accounts, unlimited if not provided
+    max_clients: Option<usize>,
+    /// Expected number of client accounts, reserved up front
+    expected_clients: Option<usize>,
+    /// Number of the most recent disputable transactions kept, unlimited if not provided
+    dispute_window: Option<usize>,
+    /// Keeps the disputes received before their transaction, until it arrives
+    allow_late_resolution: bool,
+    /// Rejects the disputes of withdrawals, only the deposits can be disputed
+    deposit_disputes_only: bool,
+    /// Creates the account of an unknown client on a withdrawal, which is then rejected
+    create_on_withdrawal: bool,
+    /// Keeps applying the disputes, resolves and chargebacks to the locked accounts
+    settle_locked: bool,
+    /// Accepts the unlock operations, reinstating the locked accounts
+    allow_unlock: bool,
+    /// Accepts the exact repeats of the applied deposits and withdrawals without any change
+    idempotent: bool,
+    /// Rejects the input amounts with more than four decimal places, instead of truncating them
+    strict_precision: bool,
+    /// Accepts the quoted amounts, with the integer digits grouped by commas
+    locale_amounts: bool,
+    /// Format of the input files
+    input_format: InputFormat,
+    /// Lowest transaction ID processed, the rows below it are skipped
+    min_tx: u32,
+    /// Highest transaction ID processed, the rows above it are skipped
+    max_tx: u32,
+    /// Field delimiter of the input, and of the CSV output
+    delimiter: u8,
+    /// Whether the CSV input files start with a header line
+    has_headers: bool,
+    /// Format of the account list output
+    format: OutputFormat,
+    /// Number of decimal places of the money amounts in the output
+    precision: usize,
+    /// Rounding of the money amounts to the output precision
+    rounding: RoundingMode,
+    /// Only writes the accounts which are locked or hold any funds
+    nonzero: bool,
+    /// Only writes the accounts changed by the transactions of this run
+    changed_only: bool,
+    /// Adds the number of the applied transactions to every account in the output
+    with_counts: bool,
+    /// Adds the highest held funds of every account during the run to the output
+    with_peak_held: bool,
+    /// Columns of the account list output, in their order, the default layout if not provided
+    columns: Option<Vec<Column>>,
+    /// Number of the accounts written between the output flushes, only flushed at the end if
+    /// not provided
+    flush_every: Option<usize>,
+    /// Suppresses the per-transaction warnings, only the fatal errors are reported
+    quiet: bool,
+    /// Format of the warnings and errors written to stderr
+    log_format: LogFormat,
+    /// Number of threads processing the transactions
+    threads: usize,
+    /// Engine state snapshot to load before the processing
+    resume: Option<OsString>,
+    /// CSV file with the opening balances of the accounts, loaded before the processing
+    opening: Option<OsString>,
+    /// File to save the engine state snapshot to after the processing
+    save_state: Option<OsString>,
+    /// CSV file listing the rejected transactions
+    reject_log: Option<OsString>,
+    /// CSV file listing the open disputes at the end of the processing
+    disputes_report: Option<OsString>,
+    /// File to record the parsed transactions to, for a later replay
+    record: Option<OsString>,
+    /// Recorded transaction file to process instead of the inputs
+    replay: Option<OsString>,
+}
+
+impl Default for Args {
+    fn default() -> Args {
+        Args {
+            inputs: Vec::new(),
+            output: None,
+            fail_on_reject: true,
+            validate: false,
+            follow: false,
+            sort_by_time: false,
+            strict_schema: false,
+            capabilities: false,
+            merge: false,
+            force: false,
+            summary: false,
+            stats: false,
+            skip_bad_rows: false,
+            progress: false,
+            strict_precision: false,
+            locale_amounts: false,
+            overdraft: Amount::default(),
+            max_client_total: None,
+            withdrawal_fee: None,
+            fee_client: None,
+            max_clients: None,
+            expected_clients: None,
+            dispute_window: None,
+            allow_late_resolution: false,
+            deposit_disputes_only: false,
+            create_on_withdrawal: false,
+            settle_locked: false,
+            allow_unlock: false,
+            idempotent: false,
+            input_format: InputFormat::default(),
+            min_tx: 0,
+            max_tx: u32::MAX,
+            delimiter: b',',
+            has_headers: true,
+            format: OutputFormat::default(),
+            precision: AMOUNT_DECIMALS,
+            rounding: RoundingMode::default(),
+            nonzero: false,
+            changed_only: false,
+            with_counts: false,
+            with_peak_held: false,
+            columns: None,
+            flush_every: None,
+            quiet: false,
+            log_format: LogFormat::default(),
+            threads: 1,
+            resume: None,
+            opening: None,
+            save_state: None,
+            reject_log: None,
+            disputes_report: None,
+            record: None,
+            replay: None,
+        }
+    }
+}
+
+/// Supported formats of the account list output
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+enum OutputFormat {
+    #[default]
+    Csv,
+    Json,
+    /// Compact binary list of the client records, see [`write_accounts_bincode`]
+    Bincode,
+}
+
+impl FromStr for OutputFormat {
+    type Err = ParserError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "csv" => Ok(OutputFormat::Csv),
+            "json" => Ok(OutputFormat::Json),
+            "bincode" => Ok(OutputFormat::Bincode),
+            _ => Err(ParserError::new(&format!("Unknown output format {s}"))),
+        }
+    }
+}
+
+/// Supported formats of the diagnostics written to stderr
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+enum LogFormat {
+    #[default]
+    Text,
+    /// One JSON object per line, with the details of a rejection as separate fields
+    Json,
+}
+
+impl FromStr for LogFormat {
+    type Err = ParserError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "text" => Ok(LogFormat::Text),
+            "json" => Ok(LogFormat::Json),
+            _ => Err(ParserError::new(&format!("Unknown log format {s}"))),
+        }
+    }
+}
+
+/// Supported formats of the transaction input
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+enum InputFormat {
+    #[default]
+    Csv,
+    /// Newline-delimited JSON objects, with the same keys as the CSV columns
+    Ndjson,
+}
+
+impl FromStr for InputFormat {
+    type Err = ParserError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "csv" => Ok(InputFormat::Csv),
+            "ndjson" => Ok(InputFormat::Ndjson),
+            _ => Err(ParserError::new(&format!("Unknown input format {s}"))),
+        }
+    }
+}
+
+impl Args {
+    /// Parses the command line arguments sent to this process.
+    /// Returns an error for unknown options, or options missing their value.
+    fn parse() -> Result<Args, ParserError> {
+        let mut args = Args::default();
+        let mut argv = env::args_os().skip(1);
+        while let Some(arg) = argv.next() {
+            match arg.to_str() {
+                Some("--no-fail-on-reject") => args.fail_on_reject = false,
+                Some("--output") => args.output = Some(next_value(&mut argv, "--output")?),
+                Some("--delimiter") => {
+                    args.delimiter = parse_delimiter(&next_value(&mut argv, "--delimiter")?)?
+                }
+                Some("--follow") => args.follow = true,
+                Some("--sort-by-time") => args.sort_by_time = true,
+                Some("--strict-schema") => args.strict_schema = true,
+                Some("--capabilities") => args.capabilities = true,
+                Some("--merge") => args.merge = true,
+                Some("--force") => args.force = true,
+                Some("--no-header") => args.has_headers = false,
+                Some("--validate") => args.validate = true,
+                Some("--input-format") => {
+                    args.input_format = parse_value(&mut argv, "--input-format")?
+                }
+                Some("--format") => args.format = parse_value(&mut argv, "--format")?,
+                Some("--quiet") => args.quiet = true,
+                Some("--log-format") => args.log_format = parse_value(&mut argv, "--log-format")?,
+                Some("--nonzero") => args.nonzero = true,
+                Some("--changed-only") => args.changed_only = true,
+                Some("--with-counts") => args.with_counts = true,
+                Some("--with-peak-held") => args.with_peak_held = true,
+                Some("--columns") => {
+                    args.columns = Some(parse_columns(&next_value(&mut argv, "--columns")?)?)
+                }
+                Some("--flush-every") => {
+                    let interval = parse_value(&mut argv, "--flush-every")?;
+                    if interval == 0 {
+                        return Err(ParserError::new(
+                            &"--flush-every must be at least 1".to_string(),
+                        ));
+                    }
+                    args.flush_every = Some(interval);
+                }
+                Some("--reject-log") => {
+                    args.reject_log = Some(next_value(&mut argv, "--reject-log")?)
+                }
+                Some("--record") => args.record = Some(next_value(&mut argv, "--record")?),
+                Some("--replay") => args.replay = Some(next_value(&mut argv, "--replay")?),
+                Some("--opening") => args.opening = Some(next_value(&mut argv, "--opening")?),
+                Some("--disputes-report") => {
+                    args.disputes_report = Some(next_value(&mut argv, "--disputes-report")?)
+                }
+                Some("--resume") => args.resume = Some(next_value(&mut argv, "--resume")?),
+                Some("--save-state") => {
+                    args.save_state = Some(next_value(&mut argv, "--save-state")?)
+                }
+                Some("--summary") => args.summary = true,
+                Some("--stats") => args.stats = true,
+                Some("--skip-bad-rows") => args.skip_bad_rows = true,
+                Some("--progress") => args.progress = true,
+                Some("--strict-precision") => args.strict_precision = true,
+                Some("--locale-amounts") => args.locale_amounts = true,
+                Some("--allow-late-resolution") => args.allow_late_resolution = true,
+                Some("--deposit-disputes-only") => args.deposit_disputes_only = true,
+                Some("--create-on-withdrawal") => args.create_on_withdrawal = true,
+                Some("--settle-locked") => args.settle_locked = true,
+                Some("--allow-unlock") => args.allow_unlock = true,
+                Some("--idempotent") => args.idempotent = true,
+                Some("--min-tx") => args.min_tx = parse_value(&mut argv, "--min-tx")?,
+                Some("--max-tx") => args.max_tx = parse_value(&mut argv, "--max-tx")?,
+                Some("--expected-clients") => {
+                    args.expected_clients = Some(parse_value(&mut argv, "--expected-clients")?)
+                }
+                Some("--dispute-window") => {
+                    args.dispute_window = Some(parse_value(&mut argv, "--dispute-window")?)
+                }
+                Some("--max-clients") => {
+                    args.max_clients = Some(parse_value(&mut argv, "--max-clients")?)
+                }
+                Some("--overdraft") => {
+                    args.overdraft = parse_value(&mut argv, "--overdraft")?;
+                    if args.overdraft.is_negative() {
+                        return Err(ParserError::new(
+                            &"--overdraft can't be negative".to_string(),
+                        ));
+                    }
+                }
+                Some("--max-client-total") => {
+                    let limit: Amount = parse_value(&mut argv, "--max-client-total")?;
+                    if limit.is_negative() {
+                        return Err(ParserError::new(
+                            &"--max-client-total can't be negative".to_string(),
+                        ));
+                    }
+                    args.max_client_total = Some(limit);
+                }
+                Some("--withdrawal-fee") => {
+                    let fee: Amount = parse_value(&mut argv, "--withdrawal-fee")?;
+                    if fee.is_negative() {
+                        return Err(ParserError::new(
+                            &"--withdrawal-fee can't be negative".to_string(),
+                        ));
+                    }
+                    args.withdrawal_fee = Some(fee);
+                }
+                Some("--fee-client") => {
+                    args.fee_client = Some(parse_value(&mut argv, "--fee-client")?)
+                }
+                Some("--threads") => {
+                    args.threads = parse_value(&mut argv, "--threads")?;
+                    if args.threads == 0 {
+                        return Err(ParserError::new(
+                            &"--threads must be at least 1".to_string(),
+                        ));
+                    }
+                }
+                Some("--rounding") => {
+                    args.rounding = parse_rounding(&next_value(&mut argv, "--rounding")?)?
+                }
+                Some("--precision") => {
+                    args.precision = parse_value(&mut argv, "--precision")?;
+                    if args.precision > AMOUNT_DECIMALS {
+                        return Err(ParserError::new(&format!(
+                            "--precision must be between 0 and {AMOUNT_DECIMALS}"
+                        )));
+                    }
+                }
+                Some(opt) if opt.starts_with("--") => {
+                    return Err(ParserError::new(&format!("Unknown option {opt}")));
+                }
+                _ => args.inputs.push(arg),
+            }
+        }
+        if args.min_tx > args.max_tx {
+            return Err(ParserError::new(
+                &"--min-tx can't be greater than --max-tx".to_string(),
+            ));
+        }
+        if args.replay.is_some() && (!args.inputs.is_empty() || args.follow) {
+            return Err(ParserError::new(
+                &"--replay can't be combined with input files or --follow".to_string(),
+            ));
+        }
+        if args.merge && (args.inputs.is_empty() || args.replay.is_some() || args.follow) {
+            return Err(ParserError::new(
+                &"--merge needs the account files, and can't be combined with --replay or --follow"
+                    .to_string(),
+            ));
+        }
+        if args.merge && args.changed_only {
+            return Err(ParserError::new(
+                &"--changed-only can't be combined with --merge".to_string(),
+            ));
+        }
+        if args.force && !args.merge {
+            return Err(ParserError::new(
+                &"--force only applies to --merge".to_string(),
+            ));
+        }
+        if args.fee_client.is_some() && args.withdrawal_fee.is_none() {
+            return Err(ParserError::new(
+                &"--fee-client only applies to --withdrawal-fee".to_string(),
+            ));
+        }
+        if args.follow && args.validate {
+            return Err(ParserError::new(
+                &"--follow can't be combined with --validate".to_string(),
+            ));
+        }
+        // Every shard would apply the limit separately
+        if args.max_clients.is_some() && args.threads > 1 {
+            return Err(ParserError::new(
+                &"--max-clients can't be combined with --threads".to_string(),
+            ));
+        }
+        // The fees of each shard would be credited to a separate fee account
+        if args.withdrawal_fee.is_some() && args.threads > 1 {
+            return Err(ParserError::new(
+                &"--withdrawal-fee can't be combined with --threads".to_string(),
+            ));
+        }
+        // Every shard would keep a window of its own clients' transactions
+        if args.dispute_window.is_some() && args.threads > 1 {
+            return Err(ParserError::new(
+                &"--dispute-window can't be combined with --threads".to_string(),
+            ));
+        }
+        // A dispute of a forgotten transaction would be parked forever, waiting for it
+        if args.dispute_window.is_some() && args.allow_late_resolution {
+            return Err(ParserError::new(
+                &"--dispute-window can't be combined with --allow-late-resolution".to_string(),
+            ));
+        }
+        // Every shard would only remember the transactions of its own clients
+        if args.idempotent && args.threads > 1 {
+            return Err(ParserError::new(
+                &"--idempotent can't be combined with --threads".to_string(),
+            ));
+        }
+        if args.follow && args.threads > 1 {
+            return Err(ParserError::new(
+                &"--follow can't be combined with --threads".to_string(),
+            ));
+        }
+        // The whole input has to be read before the first transaction is processed
+        if args.follow && args.sort_by_time {
+            return Err(ParserError::new(
+                &"--follow can't be combined with --sort-by-time".to_string(),
+            ));
+        }
+        Ok(args)
+    }
+
+    /// Builds the engine configuration from the command line options.
+    /// The output options, such as the precision or the verbosity, are handled by the program
+    /// itself, since they don't affect the engine state.
+    fn engine_config(&self) -> EngineConfig {
+        EngineConfig::new()
+            .overdraft(self.overdraft)
+            .max_clients(self.max_clients)
+            .defer_early_disputes(self.allow_late_resolution)
+            .allow_withdrawal_disputes(!self.deposit_disputes_only)
+            .create_on_withdrawal(self.create_on_withdrawal)
+            .settle_locked_accounts(self.settle_locked)
+            .allow_unlock(self.allow_unlock)
+            .idempotent(self.idempotent)
+            .dispute_window(self.dispute_window)
+            .max_client_total(self.max_client_total)
+            .withdrawal_fee(self.withdrawal_fee)
+            .fee_client(self.fee_client.unwrap_or(0))
+    }
+
+    /// Returns the input files, which is the recorded file in the replay mode.
+    fn input_paths(&self) -> &[OsString] {
+        match &self.replay {
+            Some(path) => slice::from_ref(path),
+            None => &self.inputs,
+        }
+    }
+
+    /// Collects the options of the input readers. The recorded file of the replay mode is
+    /// always read with the default options.
+    fn input_options(&self) -> InputOptions {
+        if self.replay.is_some() {
+            return InputOptions::default();
+        }
+        InputOptions {
+            format: self.input_format,
+            delimiter: self.delimiter,
+            has_headers: self.has_headers,
+            strict_schema: self.strict_schema,
+        }
+    }
+
+    /// Collects the options of the account list output.
+    fn output_options(&self) -> OutputOptions {
+        OutputOptions {
+            format: self.format,
+            precision: self.precision,
+            rounding: self.rounding,
+            delimiter: self.delimiter,
+            nonzero: self.nonzero,
+            with_counts: self.with_counts,
+            with_peak_held: self.with_peak_held,
+            columns: self.columns.clone(),
+            flush_every: self.flush_every,
+        }
+    }
+
+    /// Returns the inclusive range of the transaction IDs to process.
+    fn tx_range(&self) -> RangeInclusive<u32> {
+        self.min_tx..=self.max_tx
+    }
+
+    /// Builds the input record conversion options from the command line options.
+    fn input_config(&self) -> InputConfig {
+        InputConfig::new()
+            .strict_precision(self.strict_precision)
+            .locale_amounts(self.locale_amounts)
+    }
+}
+
+/// Returns the value following a command line option, or an error if it's missing.
+fn next_value(
+    argv: &mut impl Iterator<Item = OsString>,
+    opt: &str,
+) -> Result<OsString, ParserError> {
+    argv.next()
+        .ok_or_else(|| ParserError::new(&format!("Missing value for {opt}")))
+}
+
+/// Transaction rejected either by the parser or by the engine, as written to the reject log
+#[derive(Serialize, Debug)]
+struct Rejection {
     #[serde(rename = "type")]
     op_type: String,
     client: u16,
     tx: u32,
-    amount: Option<f32>,
+    reason: &'static str,
+}
+
+/// Outcome of the processing, gathered while the transactions are applied
+#[derive(Debug, Default)]
+struct Report {
+    /// Whether the rejected transactions are collected
+    collect_rejections: bool,
+    /// Whether the malformed records are skipped, instead of stopping the processing
+    skip_bad_rows: bool,
+    /// Number of the malformed records skipped
+    skipped: usize,
+    /// Rejected transactions, in the processing order
+    rejections: Vec<Rejection>,
+    /// Number of transactions applied to the engine
+    processed: usize,
+    /// Number of records rejected either by the parser or by the engine
+    rejected: usize,
+    /// Number of the rejections due to a locked account, counted in `rejected` as well
+    rejected_locked: usize,
+    /// Whether the counter of the rows read is shown while processing
+    show_progress: bool,
+    /// Processing statistics, only gathered when requested, to keep the timing out of the way
+    stats: Option<Stats>,
+    /// Writer of the parsed transactions, if they are recorded
+    recorder: Option<csv::Writer<File>>,
 }
 
-/// Returns a positional command line argument sent to this process.
-/// If there's no arguments, returns an error.
-fn get_nth_arg(n: usize) -> Result<OsString, Box<dyn Error>> {
-    match env::args_os().nth(n) {
-        None => Err(From::from(format!("Missing cmd line argument #{n}"))),
-        Some(file_path) => Ok(file_path),
+impl Report {
+    fn new(collect_rejections: bool, collect_stats: bool) -> Report {
+        Report {
+            collect_rejections,
+            stats: collect_stats.then(Stats::default),
+            ..Report::default()
+        }
+    }
+
+    /// Passes a well-formed record through. A malformed record stops the processing, unless
+    /// the bad rows are skipped, in which case it's reported and None is returned instead.
+    fn check_record(
+        &mut self,
+        record: Result<OperationInput, ParserError>,
+    ) -> Result<Option<OperationInput>, ParserError> {
+        match record {
+            Ok(l) => Ok(Some(l)),
+            Err(err) if err.malformed && self.skip_bad_rows => {
+                warn!("{err}");
+                self.skipped += 1;
+                Ok(None)
+            }
+            Err(err) => Err(err),
+        }
+    }
+
+    /// Registers a transaction applied to the engine.
+    fn accept(&mut self) {
+        self.processed += 1;
+    }
+
+    /// Registers a rejected transaction.
+    fn reject(&mut self, op_type: &str, client: u16, tx: u32, reason: &'static str) {
+        self.rejected += 1;
+        if self.collect_rejections {
+            self.rejections.push(Rejection {
+                op_type: op_type.to_string(),
+                client,
+                tx,
+                reason,
+            });
+        }
+    }
+
+    /// Merges the outcome of another part of the processing, e.g. a shard thread.
+    fn merge(&mut self, other: Report) {
+        self.rejections.extend(other.rejections);
+        self.processed += other.processed;
+        self.rejected += other.rejected;
+        self.rejected_locked += other.rejected_locked;
+        self.skipped += other.skipped;
+        if let (Some(stats), Some(other)) = (&mut self.stats, other.stats) {
+            stats.merge(other);
+        }
     }
 }
 
-fn find_client_by_id(clients: &mut [Client], client_id: u16) -> Option<&mut Client> {
-    clients.iter_mut().find(|c| c.id == client_id)
+/// Counter of the input rows read, shown on stderr, updated in place while processing
+#[derive(Debug)]
+struct Progress {
+    rows: u64,
+    /// Time of the last update, which throttles the updates of a fast run
+    updated: Instant,
 }
 
-fn is_same_tx_id(tx: &Transaction, trans_id: u32) -> bool {
-    match tx {
-        Transaction::Deposit(_, list_trans_id, _) => *list_trans_id == trans_id,
-        Transaction::Withdrawal(_, list_trans_id, _) => *list_trans_id == trans_id,
-        _ => false,
+impl Progress {
+    fn new() -> Progress {
+        Progress {
+            rows: 0,
+            updated: Instant::now(),
+        }
+    }
+
+    /// Counts a row read, updating the counter once in a while. The clock is only checked
+    /// every [`PROGRESS_ROWS`] rows, to keep the counting cheap.
+    fn tick(&mut self) {
+        self.rows += 1;
+        if self.rows.is_multiple_of(PROGRESS_ROWS) && self.updated.elapsed() >= PROGRESS_DELAY {
+            eprint!("\rProcessed {} rows", self.rows);
+            self.updated = Instant::now();
+        }
+    }
+
+    /// Writes the final count, ending the line of the counter
+    fn finish(&self) {
+        eprintln!("\rProcessed {} rows", self.rows);
     }
 }
 
-fn find_operation_by_id(transactions: &[Transaction], trans_id: u32) -> Option<&Transaction> {
-    transactions.iter().find(|&tx| is_same_tx_id(tx, trans_id))
+/// Count and cumulative processing time of the transactions of a single type
+#[derive(Debug, Default, Clone, Copy)]
+struct OperationStats {
+    count: usize,
+    time: Duration,
 }
 
-fn remove_operation_by_id(transactions: &mut Vec<Transaction>, trans_id: u32) {
-    let mut i = 0;
-    while i < transactions.len() {
-        if is_same_tx_id(&transactions[i], trans_id) {
-            transactions.remove(i);
-            break;
-        } else {
-            i += 1;
+/// Throughput statistics of the processing, broken down by the transaction type
+#[derive(Debug, Default)]
+struct Stats {
+    /// Statistics of the transactions fed to the engine, by the transaction type
+    operations: BTreeMap<&'static str, OperationStats>,
+    /// Wall time of the processing loop, including the input parsing
+    elapsed: Duration,
+}
+
+impl Stats {
+    /// Registers a transaction fed to the engine, whether it was applied or rejected.
+    fn record(&mut self, op: &'static str, time: Duration) {
+        let stats = self.operations.entry(op).or_default();
+        stats.count += 1;
+        stats.time += time;
+    }
+
+    /// Merges the statistics of another part of the processing, e.g. a shard thread.
+    /// The wall time of the parts is not summed, since they run concurrently.
+    fn merge(&mut self, other: Stats) {
+        for (op, other) in other.operations {
+            let stats = self.operations.entry(op).or_default();
+            stats.count += other.count;
+            stats.time += other.time;
         }
     }
 }
 
-/// Attempts to perform a disputed operation on the specified client.
-/// Returns true in case of success, or false if the dispute cannot be aplied.
-fn apply_dispute(client: &mut Client, transaction: &Transaction) -> bool {
-    match transaction {
-        Transaction::Deposit(cl_id, tx_id, tx_amount) => {
-            if *cl_id != client.id {
-                eprintln!(
-                    "DISPUTE #{} client mismatch exp:{} act:{}",
-                    tx_id, client.id, cl_id
-                );
-                return false;
-            }
-            if client.locked {
-                eprintln!(
-                    "DISPUTE #{} can't be applied to a locked account {}",
-                    tx_id, client.id
-                );
-                return false;
-            }
-            if client.available < *tx_amount {
-                eprintln!(
-                    "DISPUTE #{} client lacks funds {} < {}",
-                    tx_id, client.available, tx_amount
-                );
-                return false;
-            }
-            client.available -= tx_amount;
-            client.held += tx_amount;
-        }
-        Transaction::Withdrawal(cl_id, tx_id, tx_amount) => {
-            if *cl_id != client.id {
-                eprintln!(
-                    "DISPUTE #{} client mismatch exp:{} act:{}",
-                    tx_id, client.id, cl_id
-                );
-                return false;
+/// Transaction together with the currency of its account, None standing for the default one
+type CurrencyTransaction = (Transaction, Option<Currency>);
+
+/// Converts a CSV record to the corresponding transaction, along with its currency.
+fn convert_record(
+    l: &OperationInput,
+    config: &InputConfig,
+) -> Result<CurrencyTransaction, InputError> {
+    Ok((l.to_transaction_with(config)?, l.currency()?))
+}
+
+/// Converts a CSV record to the corresponding transaction, along with its currency, and writes
+/// it to the transaction record if there's one.
+/// Returns None, after reporting the reason, if the record doesn't describe a valid transaction.
+/// Fails only if the transaction record can't be written.
+fn parse_transaction(
+    l: OperationInput,
+    config: &InputConfig,
+    report: &mut Report,
+) -> Result<Option<CurrencyTransaction>, ParserError> {
+    match convert_record(&l, config) {
+        Ok(transaction) => {
+            if let Some(recorder) = &mut report.recorder {
+                recorder
+                    .serialize(&transaction)
+                    .map_err(|err| ParserError::new(&format!("Can't record transaction: {err}")))?;
             }
-            if client.locked {
-                eprintln!(
-                    "DISPUTE #{} can't be applied to a locked account {}",
-                    tx_id, client.id
-                );
-                return false;
+            Ok(Some(transaction))
+        }
+        Err(err) => {
+            let (op, tx, client, reason) = (l.operation_type(), l.tx, l.client, err.code());
+            match &err {
+                // The error lists the bytes of a name with any hidden characters
+                InputError::UnknownOperation(name)
+                    if name.bytes().all(|b| b.is_ascii_graphic()) =>
+                {
+                    warn!(op, tx, client, reason; "Unknown operation: {name}")
+                }
+                _ => warn!(op, tx, client, reason; "{} #{} {}", op.to_uppercase(), tx, err),
             }
-            client.held += tx_amount;
-            client.total += tx_amount;
+            report.reject(op, client, tx, reason);
+            Ok(None)
         }
-        _ => return false,
     }
-    true
 }
 
-/// Attempts to resolve the disputed operation on the specified client.
-/// Returns true in case of success, or false if the operation cannot be aplied.
-fn apply_resolve(client: &mut Client, transaction: &Transaction) -> bool {
-    match transaction {
-        Transaction::Deposit(cl_id, tx_id, tx_amount) => {
-            if *cl_id != client.id {
-                eprintln!(
-                    "RESOLVE #{} client mismatch exp:{} act:{}",
-                    tx_id, client.id, cl_id
-                );
-                return false;
-            }
-            if client.locked {
-                eprintln!(
-                    "RESOLVE #{} can't be applied to a locked account {}",
-                    tx_id, client.id
-                );
-                return false;
-            }
-            assert!(client.held >= *tx_amount);
-            client.held -= tx_amount;
-            client.available += tx_amount;
-        }
-        Transaction::Withdrawal(cl_id, tx_id, tx_amount) => {
-            if *cl_id != client.id {
-                eprintln!(
-                    "RESOLVE #{} client mismatch exp:{} act:{}",
-                    tx_id, client.id, cl_id
-                );
-                return false;
-            }
-            if client.locked {
-                eprintln!(
-                    "RESOLVE #{} can't be applied to a locked account {}",
-                    tx_id, client.id
-                );
-                return false;
+/// Creates the file of the `--record` option, writing the header of the regular CSV input
+/// format, with the currency column added, so it can be read back with `--replay`.
+fn open_recorder(path: &OsStr) -> csv::Result<csv::Writer<File>> {
+    // The transaction and its currency are written as a pair, which has no header of its own
+    let mut recorder = csv::WriterBuilder::new()
+        .has_headers(false)
+        .from_path(path)?;
+    recorder.write_record(["type", "client", "tx", "amount", "currency"])?;
+    Ok(recorder)
+}
+
+/// Feeds a single transaction to the engine, reporting the reason if it's rejected.
+fn apply_transaction(
+    engine: &mut TransactionEngine,
+    (transaction, currency): CurrencyTransaction,
+    report: &mut Report,
+) {
+    let op = transaction.name();
+    let client_id = transaction.client_id();
+    let tx_id = transaction.tx_id();
+    let is_unlock = matches!(transaction, Transaction::Unlock(..));
+    let started = report.stats.is_some().then(Instant::now);
+    let result = engine.process_in(transaction, currency);
+    if let (Some(stats), Some(started)) = (&mut report.stats, started) {
+        stats.record(op, started.elapsed());
+    }
+    match result {
+        Ok(()) => {
+            // A reinstated account is worth noting, unlike the other accepted transactions
+            if is_unlock {
+                warn!(op, tx = tx_id, client = client_id;
+                    "UNLOCK #{tx_id} reinstated the account of client {client_id}");
             }
-            assert!(client.held >= *tx_amount); // Sanity check, shouldn't happen
-            client.held -= tx_amount;
-            assert!(client.total >= *tx_amount); // Sanity check, shouldn't happen
-            client.total -= tx_amount;
+            report.accept()
         }
-        _ => return false,
+        Err(err) => report_rejection(op, client_id, tx_id, &err, report),
     }
-    true
 }
 
-/// Applies a chargeback operation on the specified client.
-/// Returns true in case of success, or false if the operation cannot be aplied.
-fn apply_chargeback(client: &mut Client, transaction: &Transaction) -> bool {
-    match transaction {
-        Transaction::Deposit(cl_id, tx_id, tx_amount) => {
-            if *cl_id != client.id {
-                eprintln!(
-                    "CHARGEBACK #{} client mismatch exp:{} act:{}",
-                    tx_id, client.id, cl_id
-                );
-                return false;
-            }
-            if client.locked {
-                eprintln!(
-                    "CHARGEBACK #{} can't be applied to a locked account {}",
-                    tx_id, client.id
-                );
-                return false;
-            }
-            assert!(client.held >= *tx_amount); // Sanity check, shouldn't happen
-            client.held -= tx_amount;
-            assert!(client.total >= *tx_amount); // Sanity check, shouldn't happen
-            client.total -= tx_amount;
-        }
-        Transaction::Withdrawal(cl_id, tx_id, tx_amount) => {
-            if *cl_id != client.id {
-                eprintln!(
-                    "Chargeback transaction #{} client mismatch exp:{} act:{}",
-                    tx_id, client.id, cl_id
-                );
-                return false;
-            }
-            if client.locked {
-                eprintln!(
-                    "CHARGEBACK #{} can't be applied to a locked account {}",
-                    tx_id, client.id
-                );
-                return false;
+/// Reports a transaction rejected by the engine.
+fn report_rejection(
+    op: &str,
+    client_id: u16,
+    tx_id: u32,
+    err: &TransactionError,
+    report: &mut Report,
+) {
+    let reason = err.code();
+    warn!(op, tx = tx_id, client = client_id, reason;
+        "{} #{} {}", op.to_uppercase(), tx_id, err);
+    // Still sending to a frozen account is an upstream issue rather than a data error
+    if let TransactionError::AccountLocked(_) = err {
+        report.rejected_locked += 1;
+    }
+    report.reject(op, client_id, tx_id, reason);
+}
+
+/// Reads all the records into memory and sorts them by their timestamp, breaking the ties by the
+/// transaction ID. The records without a timestamp come first, keeping their input order.
+/// A record with an invalid timestamp is reported and dropped, while a malformed record stops
+/// the reading, unless the bad rows are skipped.
+fn sort_by_time<I>(records: I, report: &mut Report) -> Result<Vec<OperationInput>, ParserError>
+where
+    I: Iterator<Item = Result<OperationInput, ParserError>>,
+{
+    let mut sorted = Vec::new();
+    for record in records {
+        let Some(l) = report.check_record(record)? else {
+            continue;
+        };
+        match l.timestamp() {
+            Ok(timestamp) => sorted.push((timestamp.map(|timestamp| (timestamp, l.tx)), l)),
+            Err(err) => {
+                let (op, tx, client, reason) = (l.operation_type(), l.tx, l.client, err.code());
+                warn!(op, tx, client, reason; "{} #{} {}", op.to_uppercase(), tx, err);
+                report.reject(op, client, tx, reason);
             }
-            assert!(client.held >= *tx_amount); // Sanity check, shouldn't happen
-            client.held -= tx_amount;
-            client.available += tx_amount;
         }
-        _ => return false,
     }
-    client.locked = true;
-    true
+    // The sort is stable, so the records without a timestamp stay in the input order
+    sorted.sort_by_key(|(key, _)| *key);
+    Ok(sorted.into_iter().map(|(_, l)| l).collect())
 }
 
-/// Processes a single transaction, while updating the list of clients, disputable operations, and disputes
-fn process_transaction(
-    transaction: &Transaction,
-    clients: &mut Vec<Client>,
-    operations: &mut Vec<Transaction>,
-    disputes: &mut Vec<Transaction>,
-) {
-    match transaction {
-        Transaction::Deposit(client_id, tx_id, amount) => {
-            let client = find_client_by_id(clients, *client_id);
-            match client {
-                Some(cl) => {
-                    if cl.locked {
-                        eprintln!(
-                            "DEPOSIT #{} can't be applied to a locked account {}",
-                            tx_id, cl.id
-                        );
-                        return;
-                    }
-                    cl.available += amount;
-                    cl.total += amount;
+/// Processes a stream of string transactions, parsed by the serde, feeding them to the engine
+/// which builds the client accounts according to the operations in the transaction list.
+/// The records are consumed one by one, and the first record that can't be parsed stops the
+/// processing with an error. With more than one thread, the processing is sharded by client.
+/// The records with the transaction ID outside of the given range are skipped, as if they
+/// weren't in the input, so the disputes of the skipped transactions are rejected as unknown.
+fn process_transaction_list<I>(
+    engine: &mut TransactionEngine,
+    records: I,
+    config: &InputConfig,
+    tx_range: &RangeInclusive<u32>,
+    threads: usize,
+    report: &mut Report,
+) -> Result<(), ParserError>
+where
+    I: Iterator<Item = Result<OperationInput, ParserError>>,
+{
+    let show_progress = report.show_progress;
+    let mut progress = Progress::new();
+    let started = Instant::now();
+    let result = {
+        // The malformed records are kept, so they still stop the processing
+        let mut records = records
+            .inspect(|_| {
+                if show_progress {
+                    progress.tick();
                 }
-                None => {
-                    // If the client is not found, neet to create a new record for it.
-                    clients.push(Client {
-                        id: *client_id,
-                        available: *amount,
-                        held: 0.0,
-                        total: *amount,
-                        locked: false,
-                    })
-                }
-            }
-            // Deposit is always accepted, and registered in the disputable list
-            operations.push(Transaction::Deposit(*client_id, *tx_id, *amount));
-        }
-        Transaction::Withdrawal(client_id, tx_id, amount) => {
-            let client = find_client_by_id(clients, *client_id);
-            match client {
-                Some(cl) => {
-                    if cl.locked {
-                        eprintln!(
-                            "WITHDRAWAL #{} can't be applied to a locked account {}",
-                            tx_id, cl.id
-                        );
-                        return;
-                    }
-                    if cl.available < *amount {
-                        eprintln!(
-                            "WITHDRAWAL #{} doesn't have enough funds ({} < {})",
-                            tx_id, cl.available, amount
-                        );
-                        return;
+            })
+            .filter(|record| record.as_ref().map_or(true, |l| tx_range.contains(&l.tx)));
+        if threads > 1 {
+            process_transaction_shards(engine, records, config, threads, report)
+        } else {
+            records.try_for_each(|record| {
+                if let Some(l) = report.check_record(record)? {
+                    if let Some(transaction) = parse_transaction(l, config, report)? {
+                        apply_transaction(engine, transaction, report);
                     }
-                    cl.available -= *amount;
-                    cl.total -= *amount;
-                    // Only register the withdrawal in disputable list if it was successful
-                    operations.push(transaction.clone());
                 }
-                None => {
-                    eprintln!("WITHDRAWAL #{} unknown client {}", tx_id, client_id);
-                }
-            }
+                Ok(())
+            })
         }
-        Transaction::Dispute(client_id, tx_id) => {
-            let client = find_client_by_id(clients, *client_id);
-            match client {
-                Some(cl) => {
-                    let operation = find_operation_by_id(operations, *tx_id);
-                    match operation {
-                        Some(dispute_tx) => {
-                            if apply_dispute(cl, dispute_tx) {
-                                // Remember the operation in the dispute list for later settlement
-                                disputes.push(dispute_tx.clone());
-                            }
-                        }
-                        None => {
-                            eprintln!("DISPUTE transaction #{} unknown or invalid", tx_id);
-                            return;
-                        }
+    };
+    if show_progress {
+        progress.finish();
+    }
+    if let Some(stats) = &mut report.stats {
+        stats.elapsed += started.elapsed();
+    }
+    result
+}
+
+/// Processes a stream of string transactions on a number of threads, each one owning a shard
+/// of the clients, selected by the client ID modulo the number of threads. This works since the
+/// disputes always refer to the transactions of the same client. The transactions of a client
+/// are processed in their original order, but the warnings of different shards may interleave,
+/// and the rejections of the engine are reported grouped by shard.
+/// The engine state is split between the shards, and merged back once the input is consumed.
+///
+/// A transaction ID reused by another client would be registered by both shards, so the first
+/// client using an ID is remembered here, and the disputes, resolves and chargebacks of that ID
+/// coming from another client are rejected before reaching a shard, as the engine does. Once
+/// merged, the engine keeps the first client's transaction of a reused ID as well.
+fn process_transaction_shards<I>(
+    engine: &mut TransactionEngine,
+    records: I,
+    config: &InputConfig,
+    threads: usize,
+    report: &mut Report,
+) -> Result<(), ParserError>
+where
+    I: Iterator<Item = Result<OperationInput, ParserError>>,
+{
+    let mut tx_clients: HashMap<u32, u16> = engine.transaction_clients().collect();
+    let mut reused = HashSet::new();
+    let shards = mem::take(engine).into_shards(threads);
+    thread::scope(|scope| {
+        let mut senders = Vec::with_capacity(threads);
+        let mut workers = Vec::with_capacity(threads);
+        for mut shard in shards {
+            let (sender, receiver) =
+                mpsc::sync_channel::<Vec<CurrencyTransaction>>(SHARD_QUEUE_LEN);
+            senders.push(sender);
+            let mut shard_report = Report::new(report.collect_rejections, report.stats.is_some());
+            workers.push(scope.spawn(move || {
+                for batch in receiver {
+                    for transaction in batch {
+                        apply_transaction(&mut shard, transaction, &mut shard_report);
                     }
-                    // Once the dispute is handled, the same operation can no longer be "challenged" again
-                    remove_operation_by_id(operations, *tx_id);
                 }
-                None => {
-                    eprintln!("DISPUTE unknown client {}", client_id);
-                }
-            }
+                (shard, shard_report)
+            }));
         }
-        Transaction::Resolve(client_id, tx_id) => {
-            let client = find_client_by_id(clients, *client_id);
-            match client {
-                Some(cl) => {
-                    let operation = find_operation_by_id(disputes, *tx_id);
-                    match operation {
-                        Some(resolve_tx) => {
-                            if apply_resolve(cl, resolve_tx) {
-                                // Once the dispute is resolved, the operation can no longer be "finalized" again
-                                remove_operation_by_id(disputes, *tx_id);
-                            }
-                        }
-                        None => {
-                            eprintln!("RESOLVE transaction #{} unknown or invalid", tx_id);
-                        }
-                    }
+        // Transactions are sent to the shards in batches, to reduce the synchronization cost
+        let mut batches: Vec<Vec<CurrencyTransaction>> = vec![Vec::new(); threads];
+        let mut result = Ok(());
+        for record in records {
+            let parsed = report
+                .check_record(record)
+                .and_then(|l| l.map_or(Ok(None), |l| parse_transaction(l, config, report)));
+            let transaction = match parsed {
+                Ok(Some(transaction)) => transaction,
+                Ok(None) => continue,
+                Err(err) => {
+                    result = Err(err);
+                    break;
                 }
-                None => {
-                    eprintln!("RESOLVE unknown client {}", client_id);
+            };
+            let (client_id, tx_id) = (transaction.0.client_id(), transaction.0.tx_id());
+            match transaction.0 {
+                Transaction::Deposit(..) | Transaction::Withdrawal(..) => {
+                    if *tx_clients.entry(tx_id).or_insert(client_id) != client_id {
+                        reused.insert(tx_id);
+                    }
                 }
+                Transaction::Unlock(..) => {}
+                _ => match tx_clients.get(&tx_id) {
+                    Some(&tx_client_id) if tx_client_id != client_id => {
+                        let err = TransactionError::ClientMismatch {
+                            expected: client_id,
+                            actual: tx_client_id,
+                        };
+                        report_rejection(transaction.0.name(), client_id, tx_id, &err, report);
+                        continue;
+                    }
+                    _ => {}
+                },
+            }
+            let shard = usize::from(client_id) % threads;
+            batches[shard].push(transaction);
+            if batches[shard].len() >= SHARD_BATCH_SIZE {
+                let batch = mem::take(&mut batches[shard]);
+                senders[shard].send(batch).expect("Shard thread terminated");
             }
         }
-        Transaction::Chargeback(client_id, tx_id) => {
-            let client = find_client_by_id(clients, *client_id);
-            match client {
-                Some(cl) => {
-                    let operation = find_operation_by_id(disputes, *tx_id);
-                    match operation {
-                        Some(chargeback_tx) => {
-                            if apply_chargeback(cl, chargeback_tx) {
-                                // Once the dispute is resolved, the operation can no longer be "finalized" again
-                                remove_operation_by_id(disputes, *tx_id);
-                            }
-                        }
-                        None => {
-                            eprintln!("CHARGEBACK transaction #{} unknown or invalid", tx_id);
-                        }
-                    }
-                }
-                None => {
-                    eprintln!("CHARGEBACK unknown client {}", client_id);
-                }
+        for (sender, batch) in senders.into_iter().zip(batches) {
+            if !batch.is_empty() {
+                sender.send(batch).expect("Shard thread terminated");
             }
         }
+        let mut shards = Vec::with_capacity(threads);
+        for worker in workers {
+            let (shard, shard_report) = worker.join().expect("Shard thread panicked");
+            shards.push(shard);
+            report.merge(shard_report);
+        }
+        // A reused ID keeps the transaction of its first client, unless that one was rejected
+        let mut first_registered = HashSet::new();
+        if !reused.is_empty() {
+            first_registered.extend(
+                shards
+                    .iter()
+                    .flat_map(TransactionEngine::transaction_clients)
+                    .filter(|(tx_id, client_id)| {
+                        reused.contains(tx_id) && tx_clients.get(tx_id) == Some(client_id)
+                    })
+                    .map(|(tx_id, _)| tx_id),
+            );
+        }
+        for mut shard in shards {
+            if !first_registered.is_empty() {
+                shard.retain_transactions(|tx_id, client_id| {
+                    !first_registered.contains(&tx_id) || tx_clients.get(&tx_id) == Some(&client_id)
+                });
+            }
+            engine.merge(shard);
+        }
+        result
+    })
+}
+
+/// Parses the value following a command line option, or returns an error if it's missing or invalid.
+fn parse_value<T>(argv: &mut impl Iterator<Item = OsString>, opt: &str) -> Result<T, ParserError>
+where
+    T: FromStr,
+    T::Err: fmt::Display,
+{
+    let value = next_value(argv, opt)?;
+    let value = value.to_string_lossy();
+    value
+        .parse()
+        .map_err(|err| ParserError::new(&format!("Invalid value {value} for {opt}: {err}")))
+}
+
+/// Converts the comma-separated list of the output columns given in the command line, each
+/// column listed at most once.
+fn parse_columns(value: &OsStr) -> Result<Vec<Column>, ParserError> {
+    let value = value.to_string_lossy();
+    let mut columns = Vec::new();
+    for name in value.split(',').map(str::trim) {
+        let column = Column::ALL
+            .into_iter()
+            .find(|column| column.name() == name)
+            .ok_or_else(|| {
+                let names: Vec<&str> = Column::ALL.iter().map(|column| column.name()).collect();
+                ParserError::new(&format!(
+                    "--columns must list some of {}, got '{}'",
+                    names.join(", "),
+                    name
+                ))
+            })?;
+        if columns.contains(&column) {
+            return Err(ParserError::new(&format!(
+                "--columns lists '{name}' more than once"
+            )));
+        }
+        columns.push(column);
     }
+    Ok(columns)
 }
 
-/// Processes a list of string transactions, parsed by the serde, while building a list of clients
-/// according to the operations in the transaction list.
-fn process_transaction_list(clients: &mut Vec<Client>, lst: Vec<OperationInput>) {
-    let mut transactions: Vec<Transaction> = Vec::new(); // Keeps the transactions that can be disputed
-    let mut disputes: Vec<Transaction> = Vec::new(); // Keeps the list of disputed transactions
-    for l in lst {
-        let transaction: Transaction;
-        let op_str = l.op_type.as_str();
-        match op_str {
-            // Need to convert from string representation to an Enum
-            "deposit" => match l.amount {
-                Some(amount) => {
-                    transaction = Transaction::Deposit(l.client, l.tx, amount);
+/// Converts the rounding mode given in the command line.
+fn parse_rounding(value: &OsStr) -> Result<RoundingMode, ParserError> {
+    match value.to_str() {
+        Some("trunc") => Ok(RoundingMode::Truncate),
+        Some("half-even") => Ok(RoundingMode::HalfEven),
+        Some("half-up") => Ok(RoundingMode::HalfUp),
+        _ => Err(ParserError::new(&format!(
+            "--rounding must be one of trunc, half-even or half-up, got '{}'",
+            value.to_string_lossy()
+        ))),
+    }
+}
+
+/// Converts the delimiter given in the command line to a single byte, as needed by the CSV reader.
+fn parse_delimiter(value: &OsStr) -> Result<u8, ParserError> {
+    match value.to_str().map(str::as_bytes) {
+        Some(&[delimiter]) if delimiter.is_ascii() => Ok(delimiter),
+        _ => Err(ParserError::new(&format!(
+            "--delimiter must be a single ASCII character, got '{}'",
+            value.to_string_lossy()
+        ))),
+    }
+}
+
+/// Checks the well-formedness of the input without processing it: every record has to parse to
+/// a valid transaction, the deposit and withdrawal IDs have to be unique, and the disputes,
+/// resolves and chargebacks have to refer to an earlier transaction of the same client.
+/// Each problem is reported as a warning, and the number of problems is returned.
+fn validate_transaction_list<I>(records: I, config: &InputConfig) -> usize
+where
+    I: Iterator<Item = Result<OperationInput, ParserError>>,
+{
+    let mut problems = 0;
+    let mut records_checked = 0;
+    let mut transactions: HashMap<u32, u16> = HashMap::new();
+    for record in records {
+        records_checked += 1;
+        let l = match record {
+            Ok(l) => l,
+            Err(err) => {
+                warn!("{}", err);
+                problems += 1;
+                continue;
+            }
+        };
+        let transaction = match convert_record(&l, config) {
+            Ok((transaction, _)) => transaction,
+            Err(err) => {
+                warn!("{} #{} {}", l.operation_type().to_uppercase(), l.tx, err);
+                problems += 1;
+                continue;
+            }
+        };
+        let op = transaction.name().to_uppercase();
+        let client_id = transaction.client_id();
+        let tx_id = transaction.tx_id();
+        match transaction {
+            Transaction::Unlock(..) => {}
+            Transaction::Deposit(..) | Transaction::Withdrawal(..) => {
+                if transactions.insert(tx_id, client_id).is_some() {
+                    warn!("{op} #{tx_id} repeats an earlier transaction ID");
+                    problems += 1;
+                }
+            }
+            _ => match transactions.get(&tx_id) {
+                Some(&tx_client_id) if tx_client_id == client_id => {}
+                Some(&tx_client_id) => {
+                    warn!("{op} #{tx_id} refers to a transaction of client {tx_client_id}");
+                    problems += 1;
                 }
                 None => {
-                    eprintln!("DEPOSIT #{} missing amount", l.tx);
-                    continue;
+                    warn!("{op} #{tx_id} refers to an unknown transaction");
+                    problems += 1;
                 }
             },
-            "withdrawal" => match l.amount {
-                Some(amount) => {
-                    transaction = Transaction::Withdrawal(l.client, l.tx, amount);
-                }
-                None => {
-                    eprintln!("WITHDRAWAL #{} missing amount", l.tx);
+        }
+    }
+    eprintln!("Validation: {records_checked} records checked, {problems} problems found");
+    problems
+}
+
+/// Accounts carried over from an earlier run, as they were before the processing, keyed by the
+/// client ID and currency
+type CarriedAccounts = HashMap<(u16, Option<Currency>), Client>;
+
+/// Collects the accounts present before the processing.
+fn carried_accounts(engine: &TransactionEngine) -> CarriedAccounts {
+    engine
+        .accounts()
+        .map(|cl| ((cl.id, cl.currency), cl.clone()))
+        .collect()
+}
+
+/// Returns the accounts of the engine, only keeping the ones opened or changed since the
+/// carried accounts were collected, if there are any. Any difference of the account counts as
+/// a change, whether in the balances, the lock or the transaction count, since some changes,
+/// e.g. a credited withdrawal fee, are not counted as the account's transactions.
+fn changed_accounts<'a>(
+    engine: &'a TransactionEngine,
+    carried: Option<&'a CarriedAccounts>,
+) -> impl Iterator<Item = &'a Client> {
+    engine.accounts().filter(move |cl| {
+        carried.is_none_or(|carried| {
+            carried
+                .get(&(cl.id, cl.currency))
+                .is_none_or(|previous| previous != *cl)
+        })
+    })
+}
+
+/// Processes the transactions in the follow mode, for a long-running process fed through named
+/// pipes. A `snapshot` record writes the current account state to the output, after which the
+/// processing continues. Once a named pipe input reaches its end, it's opened again to wait for
+/// the next writer, so only the regular files or stdin are ever finished.
+fn follow_transaction_files<W: io::Write>(
+    engine: &mut TransactionEngine,
+    carried: Option<&CarriedAccounts>,
+    args: &Args,
+    output: &mut W,
+    report: &mut Report,
+) -> Result<(), Box<dyn Error>> {
+    let config = args.input_config();
+    let paths: Vec<Option<&OsStr>> = if args.inputs.is_empty() {
+        vec![None]
+    } else {
+        args.inputs
+            .iter()
+            .map(|path| Some(path.as_os_str()))
+            .collect()
+    };
+    for path in paths {
+        loop {
+            let input = open_transaction_file(path, &args.input_options())?;
+            for record in input.into_records() {
+                let Some(l) = report.check_record(record)? else {
                     continue;
+                };
+                if l.operation_type().eq_ignore_ascii_case(SNAPSHOT_OP) {
+                    let clients = changed_accounts(engine, carried);
+                    dump_clients(clients, &mut *output, &args.output_options())?;
+                    output.flush()?;
+                } else if !args.tx_range().contains(&l.tx) {
+                    continue;
+                } else if let Some(transaction) = parse_transaction(l, &config, report)? {
+                    apply_transaction(engine, transaction, report);
                 }
-            },
-            "dispute" => {
-                transaction = Transaction::Dispute(l.client, l.tx);
             }
-            "resolve" => {
-                transaction = Transaction::Resolve(l.client, l.tx);
+            if !path.is_some_and(is_fifo) {
+                break;
             }
-            "chargeback" => {
-                transaction = Transaction::Chargeback(l.client, l.tx);
+        }
+    }
+    Ok(())
+}
+
+/// Checks whether the path refers to a named pipe.
+#[cfg(unix)]
+fn is_fifo(path: &OsStr) -> bool {
+    use std::os::unix::fs::FileTypeExt;
+    std::fs::metadata(path).is_ok_and(|metadata| metadata.file_type().is_fifo())
+}
+
+/// Checks whether the path refers to a named pipe, which are only handled on Unix.
+#[cfg(not(unix))]
+fn is_fifo(_path: &OsStr) -> bool {
+    false
+}
+
+/// Byte order mark, written at the start of the UTF-8 files by some tools
+const UTF8_BOM: &[u8] = b"\xef\xbb\xbf";
+
+/// Columns of the CSV input without a header, in their order
+const INPUT_COLUMNS: [&str; 6] = ["type", "client", "tx", "amount", "currency", "timestamp"];
+
+/// Options of the input readers
+#[derive(Debug, Clone, Copy)]
+struct InputOptions {
+    format: InputFormat,
+    /// Field delimiter, only applies to the CSV format
+    delimiter: u8,
+    /// Whether the first line holds the column names, only applies to the CSV format
+    has_headers: bool,
+    /// Refuses the unknown columns and the rows with a different number of fields than the
+    /// header, only applies to the CSV format
+    strict_schema: bool,
+}
+
+impl Default for InputOptions {
+    fn default() -> InputOptions {
+        InputOptions {
+            format: InputFormat::default(),
+            delimiter: b',',
+            has_headers: true,
+            strict_schema: false,
+        }
+    }
+}
+
+/// Input opened for reading the transaction records
+struct TransactionFile {
+    /// Name of the input, as used in the error messages
+    name: String,
+    reader: TransactionReader,
+}
+
+/// Reader of the transaction records in one of the supported input formats
+enum TransactionReader {
+    Csv(csv::Reader<Box<dyn io::Read>>),
+    Ndjson(BufReader<Box<dyn io::Read>>),
+}
+
+impl TransactionFile {
+    /// Turns the input into an iterator over its records. The errors are described with the
+    /// name of the input, the position of the record, and the name of the failed field.
+    fn into_records(self) -> Box<dyn Iterator<Item = Result<OperationInput, ParserError>>> {
+        let name = self.name;
+        match self.reader {
+            TransactionReader::Csv(mut reader) => {
+                // Without the header, the records are deserialized by position, so the column
+                // names only serve the error messages
+                let headers = if reader.has_headers() {
+                    reader.headers().ok().cloned()
+                } else {
+                    Some(StringRecord::from(INPUT_COLUMNS.to_vec()))
+                };
+                Box::new(reader.into_deserialize().map(move |record| {
+                    record.map_err(|err| describe_csv_error(&err, &name, &headers))
+                }))
             }
-            _ => {
-                eprintln!("Unknown operation: {op_str}");
-                continue;
+            TransactionReader::Ndjson(reader) => Box::new(
+                reader
+                    .lines()
+                    .enumerate()
+                    .filter(|(_, line)| !matches!(line, Ok(line) if line.trim().is_empty()))
+                    .map(move |(index, line)| {
+                        let line = line.map_err(|err| {
+                            ParserError::new(&format!("Can't read input {name}: {err}"))
+                        })?;
+                        serde_json::from_str(&line).map_err(|err| {
+                            ParserError::malformed(&format!(
+                                "Malformed record at line {} of {}, {}",
+                                index + 1,
+                                name,
+                                err
+                            ))
+                        })
+                    }),
+            ),
+        }
+    }
+}
+
+/// Describes a CSV error of the named input, with the position of the malformed record and,
+/// if known, the name of the field that failed to parse.
+fn describe_csv_error(err: &csv::Error, name: &str, headers: &Option<StringRecord>) -> ParserError {
+    let field_name = |index: u64| {
+        headers
+            .as_ref()
+            .and_then(|headers| headers.get(index as usize))
+            .map_or_else(|| format!("#{}", index + 1), |field| format!("'{field}'"))
+    };
+    let (pos, detail) = match err.kind() {
+        csv::ErrorKind::Deserialize {
+            pos: Some(pos),
+            err,
+        } => match err.field() {
+            Some(index) => (pos, format!("field {}: {}", field_name(index), err.kind())),
+            None => (pos, err.kind().to_string()),
+        },
+        csv::ErrorKind::Utf8 {
+            pos: Some(pos),
+            err,
+        } => (
+            pos,
+            format!("field {}: invalid UTF-8", field_name(err.field() as u64)),
+        ),
+        csv::ErrorKind::UnequalLengths {
+            pos: Some(pos),
+            expected_len,
+            len,
+        } => (pos, format!("{len} fields instead of {expected_len}")),
+        _ => return ParserError::new(&format!("Can't read input {name}: {err}")),
+    };
+    ParserError::malformed(&format!(
+        "Malformed record #{} at line {} (byte {}) of {}, {}",
+        pos.record(),
+        pos.line(),
+        pos.byte(),
+        name,
+        detail
+    ))
+}
+
+/// Opens CSV readers over all the given files, in the same order.
+/// If no file is given, a single reader over stdin is returned instead.
+/// Fails on the first file that can't be opened, before any processing starts.
+fn open_transaction_files(
+    file_paths: &[OsString],
+    options: &InputOptions,
+) -> Result<Vec<TransactionFile>, ParserError> {
+    if file_paths.is_empty() {
+        return Ok(vec![open_transaction_file(None, options)?]);
+    }
+    file_paths
+        .iter()
+        .map(|file_path| open_transaction_file(Some(file_path), options))
+        .collect()
+}
+
+/// Opens a CSV reader over the given file name.
+/// If the file name is not provided, the records are read from stdin instead.
+/// Files with the `.gz` extension are decompressed on the fly.
+/// Without the header, the columns are taken in the order of [`INPUT_COLUMNS`].
+/// If the file is not found, returns an error.
+fn open_transaction_file(
+    file_path: Option<&OsStr>,
+    options: &InputOptions,
+) -> Result<TransactionFile, ParserError> {
+    let name = file_path.map_or_else(
+        || "<stdin>".to_string(),
+        |path| path.to_string_lossy().into_owned(),
+    );
+    let input: Box<dyn io::Read> = match file_path {
+        Some(file_path) => {
+            let file = File::open(file_path)
+                .map_err(|err| ParserError::new(&format!("Can't open input file {name}: {err}")))?;
+            if Path::new(file_path).extension() == Some(OsStr::new("gz")) {
+                Box::new(GzDecoder::new(BufReader::new(file)))
+            } else {
+                Box::new(file)
             }
         }
-        process_transaction(&transaction, clients, &mut transactions, &mut disputes);
-        transactions.push(transaction);
+        None => Box::new(io::stdin()),
+    };
+    if options.format == InputFormat::Ndjson {
+        let mut reader = BufReader::new(input);
+        // A leading UTF-8 byte order mark is skipped, as the CSV reader does for its input
+        let has_bom = reader
+            .fill_buf()
+            .map_err(|err| ParserError::new(&format!("Can't read input file {name}: {err}")))?
+            .starts_with(UTF8_BOM);
+        if has_bom {
+            reader.consume(UTF8_BOM.len());
+        }
+        return Ok(TransactionFile {
+            name,
+            reader: TransactionReader::Ndjson(reader),
+        });
+    }
+    let mut reader = ReaderBuilder::new()
+        .delimiter(options.delimiter)
+        .has_headers(options.has_headers)
+        .trim(Trim::All)
+        .flexible(!options.strict_schema)
+        .from_reader(input);
+    // The header is read upfront, since the record iterator silently ignores its read errors,
+    // e.g. for a file that's not actually compressed
+    let headers = match reader.headers() {
+        Ok(headers) => headers,
+        Err(err) => {
+            return Err(ParserError::new(&format!(
+                "Can't read input file {name}: {err}"
+            )))
+        }
+    };
+    if options.strict_schema && options.has_headers {
+        let unknown: Vec<&str> = headers
+            .iter()
+            .filter(|column| !INPUT_COLUMNS.contains(column))
+            .collect();
+        if !unknown.is_empty() {
+            return Err(ParserError::new(&format!(
+                "Unknown columns {} in input file {}",
+                unknown.join(", "),
+                name
+            )));
+        }
     }
+    Ok(TransactionFile {
+        name,
+        reader: TransactionReader::Csv(reader),
+    })
 }
 
-/// Trims the float value to four digits after the decimal point
-fn round_to_4th_digit(val: f32) -> f32 {
-    ((val * 10000.0) as i64) as f32 / 10000.0
+/// Column of the account list output
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Column {
+    Client,
+    /// Currency of the account, empty for the default one
+    Currency,
+    Available,
+    Held,
+    Total,
+    Locked,
+    /// Number of the transactions applied to the account
+    TxCount,
+    /// Highest held funds of the account during the run
+    PeakHeld,
 }
 
-/// Builds a vector of CSV string record from the file name given in the first command line argument.
-/// If the file is not found, or the file name not provided, returns an error.
-fn parse_transaction_file() -> Result<Vec<OperationInput>, ParserError> {
-    let file_path = get_nth_arg(1)?;
-    let mut lines: Vec<OperationInput> = Vec::new();
-    let mut file_rdr = ReaderBuilder::new()
+impl Column {
+    const ALL: [Column; 8] = [
+        Column::Client,
+        Column::Currency,
+        Column::Available,
+        Column::Held,
+        Column::Total,
+        Column::Locked,
+        Column::TxCount,
+        Column::PeakHeld,
+    ];
+
+    /// Returns the name of the column, as written to the header
+    fn name(self) -> &'static str {
+        match self {
+            Column::Client => "client",
+            Column::Currency => "currency",
+            Column::Available => "available",
+            Column::Held => "held",
+            Column::Total => "total",
+            Column::Locked => "locked",
+            Column::TxCount => "tx_count",
+            Column::PeakHeld => "peak_held",
+        }
+    }
+
+    /// Returns the default layout: the currency column is only written if any of the accounts
+    /// has a currency, and the transaction count and peak held columns only if requested.
+    fn defaults(with_currency: bool, options: &OutputOptions) -> Vec<Column> {
+        Column::ALL
+            .into_iter()
+            .filter(|column| match column {
+                Column::Currency => with_currency,
+                Column::TxCount => options.with_counts,
+                Column::PeakHeld => options.with_peak_held,
+                _ => true,
+            })
+            .collect()
+    }
+}
+
+/// Account record as it's written to the output, with the money amounts already formatted
+#[derive(Debug)]
+struct AccountRow {
+    client: u16,
+    currency: String,
+    available: String,
+    held: String,
+    total: String,
+    locked: bool,
+    tx_count: u64,
+    peak_held: String,
+}
+
+impl AccountRow {
+    fn new(client: &Client, options: &OutputOptions) -> AccountRow {
+        let format_amount = |amount: Amount| {
+            let rounded = amount.round(options.precision, options.rounding);
+            format!("{:.*}", options.precision, rounded)
+        };
+        AccountRow {
+            client: client.id,
+            currency: client.currency.map(|c| c.to_string()).unwrap_or_default(),
+            available: format_amount(client.available),
+            held: format_amount(client.held),
+            total: format_amount(client.total),
+            locked: client.locked,
+            tx_count: client.tx_count,
+            peak_held: format_amount(client.peak_held),
+        }
+    }
+
+    /// Returns the text of the given column, as written to the CSV output
+    fn field(&self, column: Column) -> String {
+        match column {
+            Column::Client => self.client.to_string(),
+            Column::Currency => self.currency.clone(),
+            Column::Available => self.available.clone(),
+            Column::Held => self.held.clone(),
+            Column::Total => self.total.clone(),
+            Column::Locked => self.locked.to_string(),
+            Column::TxCount => self.tx_count.to_string(),
+            Column::PeakHeld => self.peak_held.clone(),
+        }
+    }
+}
+
+/// Account record limited to the given columns, serialized as an object with the fields in
+/// the order of the columns
+struct SelectedRow<'a> {
+    row: &'a AccountRow,
+    columns: &'a [Column],
+}
+
+impl Serialize for SelectedRow<'_> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let mut map = serializer.serialize_map(Some(self.columns.len()))?;
+        for &column in self.columns {
+            let row = self.row;
+            match column {
+                Column::Client => map.serialize_entry(column.name(), &row.client)?,
+                Column::Currency => map.serialize_entry(column.name(), &row.currency)?,
+                Column::Available => map.serialize_entry(column.name(), &row.available)?,
+                Column::Held => map.serialize_entry(column.name(), &row.held)?,
+                Column::Total => map.serialize_entry(column.name(), &row.total)?,
+                Column::Locked => map.serialize_entry(column.name(), &row.locked)?,
+                Column::TxCount => map.serialize_entry(column.name(), &row.tx_count)?,
+                Column::PeakHeld => map.serialize_entry(column.name(), &row.peak_held)?,
+            }
+        }
+        map.end()
+    }
+}
+
+/// Options of the account list output
+#[derive(Debug, Clone)]
+struct OutputOptions {
+    format: OutputFormat,
+    /// Number of decimal places of the money amounts
+    precision: usize,
+    /// Rounding of the money amounts to the precision
+    rounding: RoundingMode,
+    /// Field delimiter, only applies to the CSV format
+    delimiter: u8,
+    /// Skips the accounts which are not locked and have all the balances at zero
+    nonzero: bool,
+    /// Adds the transaction count column
+    with_counts: bool,
+    /// Adds the peak held funds column
+    with_peak_held: bool,
+    /// Columns to write, in their order, instead of the default layout
+    columns: Option<Vec<Column>>,
+    /// Number of the accounts written between the flushes of the output
+    flush_every: Option<usize>,
+}
+
+impl OutputOptions {
+    /// Checks whether the output is due to be flushed after the given number of accounts.
+    fn is_flush_due(&self, written: usize) -> bool {
+        self.flush_every
+            .is_some_and(|interval| written.is_multiple_of(interval))
+    }
+}
+
+/// Writes the list of records corresponding to the clients to the writer, in the given order.
+/// The money amounts are rounded to the given number of decimal places. The currency column
+/// is only written if any of the accounts has a currency, being empty for the default one,
+/// unless the columns are listed in the options, which are then written in their order.
+/// With the `nonzero` option, the unlocked accounts without any funds are skipped.
+fn dump_clients<'a, W: io::Write>(
+    clients: impl Iterator<Item = &'a Client>,
+    writer: W,
+    options: &OutputOptions,
+) -> Result<(), Box<dyn Error>> {
+    let clients: Vec<&Client> = clients.collect();
+    let with_currency = clients.iter().any(|cl| cl.currency.is_some());
+    let zero = Amount::default();
+    let clients: Vec<&Client> = clients
+        .into_iter()
+        .filter(|cl| {
+            !options.nonzero
+                || cl.locked
+                || cl.available != zero
+                || cl.held != zero
+                || cl.total != zero
+        })
+        .collect();
+    let columns = options
+        .columns
+        .clone()
+        .unwrap_or_else(|| Column::defaults(with_currency, options));
+    let rows = || -> Vec<AccountRow> {
+        clients
+            .iter()
+            .map(|cl| AccountRow::new(cl, options))
+            .collect()
+    };
+    match options.format {
+        OutputFormat::Csv => dump_clients_csv(&rows(), &columns, writer, options),
+        OutputFormat::Json => dump_clients_json(&rows(), &columns, writer, options),
+        OutputFormat::Bincode => dump_clients_bincode(&clients, writer),
+    }
+}
+
+/// Writes a CSV list of records corresponding to the clients.
+fn dump_clients_csv<W: io::Write>(
+    rows: &[AccountRow],
+    columns: &[Column],
+    writer: W,
+    options: &OutputOptions,
+) -> Result<(), Box<dyn Error>> {
+    let mut out = csv::WriterBuilder::new()
+        .delimiter(options.delimiter)
+        .has_headers(false)
+        .from_writer(writer);
+    out.write_record(columns.iter().map(|column| column.name()))?;
+    for (index, row) in rows.iter().enumerate() {
+        out.write_record(columns.iter().map(|&column| row.field(column)))?;
+        if options.is_flush_due(index + 1) {
+            out.flush()?;
+        }
+    }
+    out.flush()?;
+    Ok(())
+}
+
+/// Writes a JSON array of objects corresponding to the clients.
+/// The money amounts are written as strings, to avoid any precision loss on the consumer side.
+fn dump_clients_json<W: io::Write>(
+    rows: &[AccountRow],
+    columns: &[Column],
+    mut writer: W,
+    options: &OutputOptions,
+) -> Result<(), Box<dyn Error>> {
+    // The array is written element by element, so it can be flushed on the way
+    write!(writer, "[")?;
+    for (index, row) in rows.iter().enumerate() {
+        if index > 0 {
+            write!(writer, ",")?;
+        }
+        serde_json::to_writer(&mut writer, &SelectedRow { row, columns })?;
+        if options.is_flush_due(index + 1) {
+            writer.flush()?;
+        }
+    }
+    writeln!(writer, "]")?;
+    writer.flush()?;
+    Ok(())
+}
+
+/// Writes the client records in the bincode format, keeping the exact amounts and all the
+/// fields, so the precision and the optional columns of the text formats don't apply.
+fn dump_clients_bincode<W: io::Write>(
+    clients: &[&Client],
+    mut writer: W,
+) -> Result<(), Box<dyn Error>> {
+    write_accounts_bincode(&mut writer, clients.iter().copied())?;
+    writer.flush()?;
+    Ok(())
+}
+
+/// Opens the accounts listed in the CSV file of the opening balances, in the account list output
+/// format. The rows repeating an existing account are reported and skipped, while a malformed
+/// file, or one with the total not matching the available and held funds of any row, fails as
+/// a whole, listing the offending clients.
+fn load_opening_balances(
+    engine: &mut TransactionEngine,
+    path: &OsStr,
+    delimiter: u8,
+    report: &mut Report,
+) -> Result<(), Box<dyn Error>> {
+    let mut reader = ReaderBuilder::new()
+        .delimiter(delimiter)
         .trim(Trim::All)
-        .flexible(true)
-        .from_path(file_path)?;
-    for result in file_rdr.deserialize() {
-        let mut record: OperationInput = result?;
-        if record.amount.is_some() {
-            record.amount = Some(round_to_4th_digit(record.amount.unwrap()));
-        }
-        lines.push(record);
-    }
-    Ok(lines)
-}
-
-/// Writes a CSV list of records corresponding to the client list in clients to stdout.
-fn dump_clients(clients: &[Client]) -> Result<(), Box<dyn Error>> {
-    let mut out = csv::WriterBuilder::new().from_writer(io::stdout());
-    out.serialize(("client", "available", "held", "total", "locked"))?;
-    for cl in clients {
-        out.serialize((
-            cl.id,
-            round_to_4th_digit(cl.available),
-            round_to_4th_digit(cl.held),
-            round_to_4th_digit(cl.total),
-            cl.locked,
-        ))?;
+        .from_path(path)?;
+    let mut inconsistent = Vec::new();
+    for client in reader.deserialize() {
+        let client: Client = client?;
+        let client_id = client.id;
+        match engine.open_account(client) {
+            Ok(()) => {}
+            Err(TransactionError::InconsistentBalance { .. }) => inconsistent.push(client_id),
+            Err(err) => {
+                warn!(op = "opening", client = client_id, reason = err.code();
+                    "OPENING of client {client_id} {err}");
+                report.reject("opening", client_id, 0, err.code());
+            }
+        }
+    }
+    if !inconsistent.is_empty() {
+        inconsistent.sort_unstable();
+        inconsistent.dedup();
+        return Err(InconsistentLedger {
+            client_ids: inconsistent,
+        }
+        .into());
+    }
+    Ok(())
+}
+
+/// Writes the CSV list of the rejected transactions to the given file.
+fn write_reject_log(path: &OsStr, rejections: &[Rejection]) -> Result<(), Box<dyn Error>> {
+    let mut out = csv::WriterBuilder::new()
+        .has_headers(false)
+        .from_path(path)?;
+    out.serialize(("type", "client", "tx", "reason"))?;
+    for rejection in rejections {
+        out.serialize(rejection)?;
+    }
+    out.flush()?;
+    Ok(())
+}
+
+/// Open dispute, as written to the disputes report
+#[derive(Serialize, Debug)]
+struct DisputeRow {
+    client: u16,
+    /// Currency column, only written if any of the disputes is in a currency
+    #[serde(skip_serializing_if = "Option::is_none")]
+    currency: Option<String>,
+    tx: u32,
+    #[serde(rename = "type")]
+    op_type: &'static str,
+    amount: Amount,
+}
+
+/// Reads the account lists written by separate runs, e.g. over the shards of the transactions,
+/// and combines the accounts of every client and currency: the balances and the transaction
+/// counts are summed, and the account is locked if it's locked in any of the lists. Fails if
+/// the accounts of a client differ in their locked states, unless forced, or if a sum
+/// overflows. Returns the merged accounts sorted as in [`TransactionEngine::accounts`].
+fn merge_account_files(
+    paths: &[OsString],
+    delimiter: u8,
+    force: bool,
+) -> Result<Vec<Client>, Box<dyn Error>> {
+    let mut merged: BTreeMap<(u16, Option<Currency>), Client> = BTreeMap::new();
+    let mut conflicts = Vec::new();
+    for path in paths {
+        let name = path.to_string_lossy();
+        let mut reader = ReaderBuilder::new()
+            .delimiter(delimiter)
+            .trim(Trim::All)
+            .from_path(path)
+            .map_err(|err| format!("Can't open account file {name}: {err}"))?;
+        for client in reader.deserialize() {
+            let client: Client =
+                client.map_err(|err| format!("Malformed account in {name}: {err}"))?;
+            let Some(account) = merged.get_mut(&(client.id, client.currency)) else {
+                merged.insert((client.id, client.currency), client);
+                continue;
+            };
+            if account.locked != client.locked {
+                conflicts.push(client.id);
+            }
+            let overflow = || format!("Balances of client {} overflow", client.id);
+            account.available = account
+                .available
+                .checked_add(client.available)
+                .ok_or_else(overflow)?;
+            account.held = account.held.checked_add(client.held).ok_or_else(overflow)?;
+            account.total = account
+                .total
+                .checked_add(client.total)
+                .ok_or_else(overflow)?;
+            account.locked |= client.locked;
+            account.tx_count += client.tx_count;
+            // The peaks may have been reached at different times, so their sum is only an upper
+            // bound, while exact for a client found in a single list
+            account.peak_held = account
+                .peak_held
+                .checked_add(client.peak_held)
+                .ok_or_else(overflow)?;
+        }
+    }
+    if !conflicts.is_empty() && !force {
+        conflicts.sort_unstable();
+        conflicts.dedup();
+        let ids: Vec<String> = conflicts.iter().map(u16::to_string).collect();
+        return Err(format!(
+            "Conflicting locked states of clients {}, use --force to merge them anyway",
+            ids.join(", ")
+        )
+        .into());
+    }
+    Ok(merged.into_values().collect())
+}
+
+/// Writes the CSV list of the open disputes, which make up the held funds of the accounts, to
+/// the given file.
+fn write_disputes_report(
+    path: &OsStr,
+    engine: &TransactionEngine,
+    delimiter: u8,
+) -> Result<(), Box<dyn Error>> {
+    let disputes = engine.open_disputes();
+    let with_currency = disputes.iter().any(|dispute| dispute.currency.is_some());
+    let mut out = csv::WriterBuilder::new()
+        .delimiter(delimiter)
+        .has_headers(false)
+        .from_path(path)?;
+    if with_currency {
+        out.serialize(("client", "currency", "tx", "type", "amount"))?;
+    } else {
+        out.serialize(("client", "tx", "type", "amount"))?;
+    }
+    for dispute in disputes {
+        out.serialize(DisputeRow {
+            client: dispute.client_id,
+            currency: with_currency
+                .then(|| dispute.currency.map(|c| c.to_string()).unwrap_or_default()),
+            tx: dispute.tx_id,
+            op_type: if dispute.is_withdrawal {
+                "withdrawal"
+            } else {
+                "deposit"
+            },
+            amount: dispute.amount,
+        })?;
     }
     out.flush()?;
     Ok(())
 }
 
+/// What the binary supports, for the tools wrapping it to adapt to the deployed version
+#[derive(Serialize, Debug)]
+struct Capabilities {
+    version: &'static str,
+    operations: [&'static str; 6],
+    /// Number of decimal places kept for the money amounts
+    amount_decimals: usize,
+    max_client_id: u16,
+    max_tx_id: u32,
+    input_formats: [&'static str; 2],
+    output_formats: [&'static str; 3],
+    /// Optional features compiled into the build
+    features: Vec<&'static str>,
+}
+
+/// Writes the capabilities of the binary as a JSON object.
+fn write_capabilities<W: io::Write>(mut writer: W) -> Result<(), Box<dyn Error>> {
+    let mut features = Vec::new();
+    if cfg!(feature = "async") {
+        features.push("async");
+    }
+    let capabilities = Capabilities {
+        version: env!("CARGO_PKG_VERSION"),
+        operations: OPERATION_NAMES,
+        amount_decimals: AMOUNT_DECIMALS,
+        max_client_id: u16::MAX,
+        max_tx_id: u32::MAX,
+        input_formats: ["csv", "ndjson"],
+        output_formats: ["csv", "json", "bincode"],
+        features,
+    };
+    serde_json::to_writer_pretty(&mut writer, &capabilities)?;
+    writeln!(writer)?;
+    writer.flush()?;
+    Ok(())
+}
+
+/// Writes the aggregate totals of the accounts, together with the transaction counts, to stderr.
+fn write_summary(engine: &TransactionEngine, report: &Report) {
+    let mut clients = 0;
+    let mut locked = 0;
+    let mut last_client_id = None;
+    // The funds in different currencies are summed up separately
+    let mut available: BTreeMap<Option<Currency>, Option<Amount>> = BTreeMap::new();
+    for client in engine.accounts() {
+        if last_client_id != Some(client.id) {
+            clients += 1;
+            last_client_id = Some(client.id);
+        }
+        if client.locked {
+            locked += 1;
+        }
+        let sum = available
+            .entry(client.currency)
+            .or_insert(Some(Amount::default()));
+        *sum = sum.and_then(|sum| sum.checked_add(client.available));
+    }
+    let available: Vec<String> = available
+        .into_iter()
+        .map(|(currency, sum)| {
+            let sum = sum.map_or_else(|| "overflow".to_string(), |sum| sum.to_string());
+            match currency {
+                Some(currency) => format!("{sum} {currency}"),
+                None => sum,
+            }
+        })
+        .collect();
+    let available = if available.is_empty() {
+        Amount::default().to_string()
+    } else {
+        available.join(", ")
+    };
+    eprintln!(
+        "Summary: {clients} clients ({locked} locked), {available} available in total, \
+         {} transactions processed, {} rejected ({} on locked accounts)",
+        report.processed, report.rejected, report.rejected_locked
+    );
+}
+
+/// Writes the processing throughput statistics to stderr: the overall rate of the records, and
+/// the count and cumulative engine time of each transaction type.
+fn write_stats(stats: &Stats, report: &Report) {
+    let rows = report.processed + report.rejected;
+    let seconds = stats.elapsed.as_secs_f64();
+    let rate = if seconds > 0.0 {
+        format!("{:.0} rows/sec", rows as f64 / seconds)
+    } else {
+        "rate unknown".to_string()
+    };
+    eprintln!("Stats: {rows} rows in {seconds:.3}s, {rate}");
+    for (op, op_stats) in &stats.operations {
+        eprintln!(
+            "Stats: {op}: {} transactions, {:.3}ms in the engine",
+            op_stats.count,
+            op_stats.time.as_secs_f64() * 1000.0
+        );
+    }
+}
+
+/// Checks whether the error, or any error causing it, is a broken pipe.
+fn is_broken_pipe(err: &(dyn Error + 'static)) -> bool {
+    let mut source = Some(err);
+    while let Some(err) = source {
+        // The JSON errors don't expose the underlying I/O error as their source
+        let kind = match err.downcast_ref::<serde_json::Error>() {
+            Some(json_err) => json_err.io_error_kind(),
+            None => err.downcast_ref::<io::Error>().map(io::Error::kind),
+        };
+        if kind == Some(io::ErrorKind::BrokenPipe) {
+            return true;
+        }
+        source = err.source();
+    }
+    false
+}
+
+/// Opens the output file given in the command line, or the stdout if there's none.
+fn open_output(path: Option<&OsStr>) -> Result<Box<dyn io::Write>, ParserError> {
+    match path {
+        Some(path) => match File::create(path) {
+            Ok(file) => Ok(Box::new(file)),
+            Err(err) => Err(ParserError::new(&format!(
+                "Can't create output file {}: {}",
+                path.to_string_lossy(),
+                err
+            ))),
+        },
+        None => Ok(Box::new(io::stdout())),
+    }
+}
+
+/// Collects the key-value pairs of a log record as the fields of a JSON object.
+struct JsonFields(String);
+
+impl<'kvs> log::kv::VisitSource<'kvs> for JsonFields {
+    fn visit_pair(
+        &mut self,
+        key: log::kv::Key<'kvs>,
+        value: log::kv::Value<'kvs>,
+    ) -> Result<(), log::kv::Error> {
+        let value = match value.to_u64() {
+            Some(number) => number.to_string(),
+            None => serde_json::Value::from(value.to_string()).to_string(),
+        };
+        let key = serde_json::Value::from(key.as_str()).to_string();
+        self.0.push_str(&format!(",{key}:{value}"));
+        Ok(())
+    }
+}
+
+/// Initializes the logger, honoring the `RUST_LOG` environment variable.
+/// Without it, the warnings and errors are written to the stderr. In the JSON format, each
+/// message is written as an object with the `level` and `message` fields, followed by the
+/// `op`, `tx`, `client` and `reason` fields of a rejected transaction.
+fn init_logger(format: LogFormat) {
+    let mut builder =
+        env_logger::Builder::from_env(env_logger::Env::default().default_filter_or("warn"));
+    builder.format_timestamp(None).format_target(false);
+    if format == LogFormat::Json {
+        builder.format(|buf, record| {
+            let mut fields = JsonFields(String::new());
+            // Visiting the fields can't fail, since the visitor only collects them
+            let _ = record.key_values().visit(&mut fields);
+            writeln!(
+                buf,
+                "{{\"level\":{},\"message\":{}{}}}",
+                serde_json::Value::from(record.level().as_str().to_lowercase()),
+                serde_json::Value::from(record.args().to_string()),
+                fields.0
+            )
+        });
+    }
+    builder.init();
+}
+
 fn main() {
-    let parse_res = parse_transaction_file();
-    let mut clients: Vec<Client> = Vec::new();
-    match parse_res {
-        Ok(v) => {
-            process_transaction_list(&mut clients, v);
-            let dump_res = dump_clients(clients.as_slice());
-            if let Err(err) = dump_res {
-                eprintln!("{}", err);
+    let args = Args::parse();
+    // The parsing errors are reported in the default format
+    init_logger(
+        args.as_ref()
+            .map_or(LogFormat::default(), |args| args.log_format),
+    );
+    let args = match args {
+        Ok(args) => args,
+        Err(err) => {
+            error!("{}", err);
+            process::exit(1)
+        }
+    };
+    if args.quiet {
+        log::set_max_level(LevelFilter::Error);
+    }
+    if args.capabilities {
+        if let Err(err) = write_capabilities(io::stdout().lock()) {
+            if !is_broken_pipe(err.as_ref()) {
+                error!("{}", err);
+                process::exit(1)
+            }
+        }
+        return;
+    }
+    if args.merge {
+        let result =
+            merge_account_files(&args.inputs, args.delimiter, args.force).and_then(|clients| {
+                let output = open_output(args.output.as_deref())?;
+                dump_clients(clients.iter(), output, &args.output_options())
+            });
+        if let Err(err) = result {
+            if !is_broken_pipe(err.as_ref()) {
+                error!("{}", err);
+                process::exit(1)
+            }
+        }
+        return;
+    }
+    // In the follow mode, the inputs are opened lazily, since a named pipe blocks until written
+    let inputs = if args.follow {
+        Vec::new()
+    } else {
+        match open_transaction_files(args.input_paths(), &args.input_options()) {
+            Ok(inputs) => inputs,
+            Err(err) => {
+                error!("{}", err);
                 process::exit(1)
             }
         }
+    };
+    if args.validate {
+        let problems = validate_transaction_list(
+            inputs.into_iter().flat_map(TransactionFile::into_records),
+            &args.input_config(),
+        );
+        process::exit(if problems == 0 { 0 } else { 1 })
+    }
+    // In the validation mode the output file is not created
+    let mut output = match open_output(args.output.as_deref()) {
+        Ok(output) => output,
         Err(err) => {
-            eprintln!("{}", err);
+            error!("{}", err);
+            process::exit(1)
+        }
+    };
+    let mut engine = match &args.resume {
+        Some(path) => match TransactionEngine::load_state(path) {
+            Ok(engine) => engine,
+            Err(err) => {
+                error!("Can't load state from {}: {}", path.to_string_lossy(), err);
+                process::exit(1)
+            }
+        },
+        None => TransactionEngine::new(),
+    };
+    engine.set_config(args.engine_config());
+    if let Some(clients) = args.expected_clients {
+        engine.reserve(clients);
+    }
+    let mut report = Report::new(args.reject_log.is_some(), args.stats);
+    report.skip_bad_rows = args.skip_bad_rows;
+    report.show_progress = args.progress;
+    if let Some(path) = &args.opening {
+        if let Err(err) = load_opening_balances(&mut engine, path, args.delimiter, &mut report) {
+            error!(
+                "Can't load opening balances from {}: {}",
+                path.to_string_lossy(),
+                err
+            );
+            process::exit(1)
+        }
+    }
+    // The accounts loaded from the state or the opening balances are only written once changed
+    let carried = args.changed_only.then(|| carried_accounts(&engine));
+    if let Some(path) = &args.record {
+        match open_recorder(path) {
+            Ok(recorder) => report.recorder = Some(recorder),
+            Err(err) => {
+                error!(
+                    "Can't create record file {}: {}",
+                    path.to_string_lossy(),
+                    err
+                );
+                process::exit(1)
+            }
+        }
+    }
+    if args.follow {
+        if let Err(err) = follow_transaction_files(
+            &mut engine,
+            carried.as_ref(),
+            &args,
+            &mut output,
+            &mut report,
+        ) {
+            if is_broken_pipe(err.as_ref()) {
+                return;
+            }
+            error!("{}", err);
+            process::exit(1)
+        }
+    } else {
+        // The files are processed as a single stream, sharing the engine state
+        let records = inputs.into_iter().flat_map(TransactionFile::into_records);
+        let (config, tx_range) = (args.input_config(), args.tx_range());
+        let result = if args.sort_by_time {
+            sort_by_time(records, &mut report).and_then(|sorted| {
+                let records = sorted.into_iter().map(Ok);
+                process_transaction_list(
+                    &mut engine,
+                    records,
+                    &config,
+                    &tx_range,
+                    args.threads,
+                    &mut report,
+                )
+            })
+        } else {
+            process_transaction_list(
+                &mut engine,
+                records,
+                &config,
+                &tx_range,
+                args.threads,
+                &mut report,
+            )
+        };
+        if let Err(err) = result {
+            error!("{}", err);
+            process::exit(1)
+        }
+    }
+    if let Some(recorder) = &mut report.recorder {
+        if let Err(err) = recorder.flush() {
+            error!("Can't write record file: {}", err);
+            process::exit(1)
+        }
+    }
+    if let Some(path) = &args.reject_log {
+        if let Err(err) = write_reject_log(path, &report.rejections) {
+            error!("Can't write reject log {}: {}", path.to_string_lossy(), err);
+            process::exit(1)
+        }
+    }
+    if let Some(path) = &args.disputes_report {
+        if let Err(err) = write_disputes_report(path, &engine, args.delimiter) {
+            error!(
+                "Can't write disputes report {}: {}",
+                path.to_string_lossy(),
+                err
+            );
+            process::exit(1)
+        }
+    }
+    if let Some(path) = &args.save_state {
+        if let Err(err) = engine.save_state(path) {
+            error!("Can't save state to {}: {}", path.to_string_lossy(), err);
             process::exit(1)
         }
     }
+    let clients = changed_accounts(&engine, carried.as_ref());
+    if let Err(err) = dump_clients(clients, output, &args.output_options()) {
+        // The output closed by a downstream consumer, e.g. `transact txns.csv | head`, is not
+        // a failure, like for the other command line tools
+        if is_broken_pipe(err.as_ref()) {
+            return;
+        }
+        error!("{}", err);
+        process::exit(1)
+    }
+    if args.summary {
+        write_summary(&engine, &report);
+    }
+    if let Some(stats) = &report.stats {
+        write_stats(stats, &report);
+    }
+    if report.skipped > 0 {
+        eprintln!("Skipped {} malformed records", report.skipped);
+    }
+    if args.fail_on_reject && (report.rejected > 0 || report.skipped > 0) {
+        process::exit(REJECTED_EXIT_CODE)
+    }
 }
@@ -0,0 +1,55 @@
+use crate::Amount;
+use std::fmt;
+
+/// Money type the engine keeps the balances in. The default, [`Amount`], is a fast fixed-point
+/// type with four decimal places, but any type with checked arithmetic can be used instead,
+/// e.g. a wrapper of an arbitrary precision decimal:
+///
+/// ```
+/// use std::fmt;
+/// use transact::{Money, Transaction, TransactionEngine};
+///
+/// /// Whole cents, kept in a 128-bit integer
+/// #[derive(Debug, Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord)]
+/// struct Cents(i128);
+///
+/// impl fmt::Display for Cents {
+///     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+///         write!(f, "{}.{:02}", self.0 / 100, self.0.abs() % 100)
+///     }
+/// }
+///
+/// impl Money for Cents {
+///     fn checked_add(self, other: Cents) -> Option<Cents> {
+///         self.0.checked_add(other.0).map(Cents)
+///     }
+///
+///     fn checked_sub(self, other: Cents) -> Option<Cents> {
+///         self.0.checked_sub(other.0).map(Cents)
+///     }
+/// }
+///
+/// let mut engine = TransactionEngine::<Cents>::default();
+/// engine.process(Transaction::Deposit(1, 1, Cents(250))).unwrap();
+/// assert_eq!(engine.get_account(1).unwrap().available.to_string(), "2.50");
+/// ```
+///
+/// The [`Default`] value stands for zero, and the [`Display`](fmt::Display) implementation is
+/// used in the error messages.
+pub trait Money: Copy + Ord + Default + fmt::Debug + fmt::Display {
+    /// Adds two amounts, returning None on overflow
+    fn checked_add(self, other: Self) -> Option<Self>;
+
+    /// Subtracts two amounts, returning None on overflow
+    fn checked_sub(self, other: Self) -> Option<Self>;
+}
+
+impl Money for Amount {
+    fn checked_add(self, other: Amount) -> Option<Amount> {
+        Amount::checked_add(self, other)
+    }
+
+    fn checked_sub(self, other: Amount) -> Option<Amount> {
+        Amount::checked_sub(self, other)
+    }
+}
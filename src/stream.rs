@@ -0,0 +1,40 @@
+use crate::{OperationInput, TransactionEngine};
+use futures_util::stream::{Stream, StreamExt};
+use log::warn;
+
+/// Processes an asynchronous stream of input records, e.g. received over the network, and
+/// returns the engine holding the final account state once the stream ends. The records are
+/// applied in order, each in its currency, the same way as in
+/// [`TransactionEngine::process_in`]. Records which don't describe a valid transaction, and
+/// rejected transactions, are logged as warnings and skipped.
+///
+/// The function doesn't depend on a particular async runtime, it can be driven by tokio or
+/// any other executor.
+pub async fn process_stream<S>(stream: S) -> TransactionEngine
+where
+    S: Stream<Item = OperationInput>,
+{
+    let mut engine = TransactionEngine::new();
+    let mut stream = std::pin::pin!(stream);
+    while let Some(input) = stream.next().await {
+        let (op, tx, client) = (input.operation_type(), input.tx, input.client);
+        let result = input.currency().and_then(|currency| {
+            input
+                .to_transaction()
+                .map(|transaction| (transaction, currency))
+        });
+        match result {
+            Ok((transaction, currency)) => {
+                if let Err(err) = engine.process_in(transaction, currency) {
+                    let reason = err.code();
+                    warn!(op, tx, client, reason; "{} #{} {}", op.to_uppercase(), tx, err);
+                }
+            }
+            Err(err) => {
+                let reason = err.code();
+                warn!(op, tx, client, reason; "{} #{} {}", op.to_uppercase(), tx, err);
+            }
+        }
+    }
+    engine
+}
@@ -0,0 +1,194 @@
+use std::error::Error;
+use std::fmt;
+use std::str::FromStr;
+
+/// Point in time of a transaction, as given in an ISO-8601 timestamp, kept in UTC with the
+/// nanosecond precision, so the timestamps with different offsets compare by the actual time.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct Timestamp {
+    /// Seconds since the Unix epoch
+    seconds: i64,
+    nanos: u32,
+}
+
+impl Timestamp {
+    /// Returns the number of the whole seconds since the Unix epoch, 1970-01-01T00:00:00Z
+    pub fn unix_seconds(&self) -> i64 {
+        self.seconds
+    }
+}
+
+/// Writes the timestamp in UTC, e.g. `2024-03-01T12:30:00Z`, with the fraction of a second
+/// only if there's one.
+impl fmt::Display for Timestamp {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let (days, time) = (
+            self.seconds.div_euclid(86_400),
+            self.seconds.rem_euclid(86_400),
+        );
+        let (year, month, day) = civil_from_days(days);
+        write!(
+            f,
+            "{:04}-{:02}-{:02}T{:02}:{:02}:{:02}",
+            year,
+            month,
+            day,
+            time / 3600,
+            time / 60 % 60,
+            time % 60
+        )?;
+        if self.nanos > 0 {
+            let fraction = format!("{:09}", self.nanos);
+            write!(f, ".{}", fraction.trim_end_matches('0'))?;
+        }
+        write!(f, "Z")
+    }
+}
+
+/// Parses an ISO-8601 date, `2024-03-01`, or a date and time, `2024-03-01T12:30:00`, with the
+/// optional seconds, fraction of a second, and UTC offset, such as `Z`, `+02:00` or `-0500`.
+/// A space is accepted instead of the `T`. The timestamps without an offset are taken as UTC.
+impl FromStr for Timestamp {
+    type Err = ParseTimestampError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        parse_timestamp(s).ok_or_else(|| ParseTimestampError {
+            input: s.to_string(),
+        })
+    }
+}
+
+fn parse_timestamp(s: &str) -> Option<Timestamp> {
+    let bytes = s.as_bytes();
+    if bytes.len() < 10 || bytes[4] != b'-' || bytes[7] != b'-' {
+        return None;
+    }
+    let year = digits(&bytes[0..4])?;
+    let month = digits(&bytes[5..7])?;
+    let day = digits(&bytes[8..10])?;
+    if !(1..=12).contains(&month) || day < 1 || day > days_in_month(year, month) {
+        return None;
+    }
+    let mut seconds = days_from_civil(year, month, day) * 86_400;
+    let mut nanos = 0;
+    let mut rest = &bytes[10..];
+    if let [b'T' | b't' | b' ', time @ ..] = rest {
+        let (hour, minute) = match time {
+            [h1, h2, b':', m1, m2, ..] => (digits(&[*h1, *h2])?, digits(&[*m1, *m2])?),
+            _ => return None,
+        };
+        rest = &time[5..];
+        let second = match rest {
+            [b':', s1, s2, ..] => {
+                let second = digits(&[*s1, *s2])?;
+                rest = &rest[3..];
+                second
+            }
+            _ => 0,
+        };
+        // A leap second is not representable, as in most of the systems producing the feeds
+        if hour > 23 || minute > 59 || second > 59 {
+            return None;
+        }
+        seconds += hour * 3600 + minute * 60 + second;
+        if let [b'.' | b',', fraction @ ..] = rest {
+            let len = fraction.iter().take_while(|ch| ch.is_ascii_digit()).count();
+            if len == 0 || len > 9 {
+                return None;
+            }
+            nanos = digits(&fraction[..len])? as u32 * 10u32.pow(9 - len as u32);
+            rest = &fraction[len..];
+        }
+        seconds -= match rest {
+            [] | [b'Z' | b'z'] => 0,
+            [sign @ (b'+' | b'-'), offset @ ..] => {
+                let (hours, minutes) = match offset {
+                    [h1, h2] => (digits(&[*h1, *h2])?, 0),
+                    [h1, h2, b':', m1, m2] | [h1, h2, m1, m2] => {
+                        (digits(&[*h1, *h2])?, digits(&[*m1, *m2])?)
+                    }
+                    _ => return None,
+                };
+                if hours > 23 || minutes > 59 {
+                    return None;
+                }
+                let offset = hours * 3600 + minutes * 60;
+                if *sign == b'-' {
+                    -offset
+                } else {
+                    offset
+                }
+            }
+            _ => return None,
+        };
+    } else if !rest.is_empty() {
+        return None;
+    }
+    Some(Timestamp { seconds, nanos })
+}
+
+/// Converts a run of ASCII digits to a number, failing on any other character
+fn digits(text: &[u8]) -> Option<i64> {
+    text.iter().try_fold(0, |value: i64, ch| {
+        ch.is_ascii_digit()
+            .then(|| value * 10 + i64::from(ch - b'0'))
+    })
+}
+
+fn is_leap_year(year: i64) -> bool {
+    year % 4 == 0 && (year % 100 != 0 || year % 400 == 0)
+}
+
+fn days_in_month(year: i64, month: i64) -> i64 {
+    match month {
+        2 if is_leap_year(year) => 29,
+        2 => 28,
+        4 | 6 | 9 | 11 => 30,
+        _ => 31,
+    }
+}
+
+/// Returns the number of days since 1970-01-01 of the given date of the proleptic Gregorian
+/// calendar, following the algorithm of Howard Hinnant.
+fn days_from_civil(year: i64, month: i64, day: i64) -> i64 {
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = year.div_euclid(400);
+    let year_of_era = year - era * 400;
+    let day_of_year = (153 * ((month + 9) % 12) + 2) / 5 + day - 1;
+    let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+    era * 146_097 + day_of_era - 719_468
+}
+
+/// Returns the date of the given number of days since 1970-01-01, the inverse of
+/// [`days_from_civil`].
+fn civil_from_days(days: i64) -> (i64, i64, i64) {
+    let days = days + 719_468;
+    let era = days.div_euclid(146_097);
+    let day_of_era = days - era * 146_097;
+    let year_of_era =
+        (day_of_era - day_of_era / 1460 + day_of_era / 36_524 - day_of_era / 146_096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let month_index = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * month_index + 2) / 5 + 1;
+    let month = if month_index < 10 {
+        month_index + 3
+    } else {
+        month_index - 9
+    };
+    let year = year_of_era + era * 400 + i64::from(month <= 2);
+    (year, month, day)
+}
+
+/// Error returned when a string can't be parsed as a timestamp
+#[derive(Debug)]
+pub struct ParseTimestampError {
+    input: String,
+}
+
+impl fmt::Display for ParseTimestampError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Invalid timestamp '{}'", self.input)
+    }
+}
+
+impl Error for ParseTimestampError {}
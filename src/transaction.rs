@@ -0,0 +1,104 @@
+use crate::{Amount, Money};
+use serde::ser::{Serialize, SerializeStruct, Serializer};
+use std::fmt;
+
+/// Names of all the supported operations, as they appear in the transaction type column
+pub const OPERATION_NAMES: [&str; 6] = [
+    "deposit",
+    "withdrawal",
+    "dispute",
+    "resolve",
+    "chargeback",
+    "unlock",
+];
+
+/// Type describing the possible transactions supported by the engine.
+/// The optional amount of a dispute or chargeback limits it to a portion of the transaction.
+/// An unlock reinstates a locked account, its transaction ID only identifies the input row.
+#[derive(Debug, Clone)]
+pub enum Transaction<M = Amount> {
+    Deposit(u16, u32, M),
+    Withdrawal(u16, u32, M),
+    Dispute(u16, u32, Option<M>),
+    Resolve(u16, u32),
+    Chargeback(u16, u32, Option<M>),
+    Unlock(u16, u32),
+}
+
+impl<M: Money> Transaction<M> {
+    /// Returns the operation name, as it appears in the transaction type column
+    pub fn name(&self) -> &'static str {
+        match self {
+            Transaction::Deposit(..) => "deposit",
+            Transaction::Withdrawal(..) => "withdrawal",
+            Transaction::Dispute(..) => "dispute",
+            Transaction::Resolve(..) => "resolve",
+            Transaction::Chargeback(..) => "chargeback",
+            Transaction::Unlock(..) => "unlock",
+        }
+    }
+
+    /// Returns the ID of the client the transaction is applied to
+    pub fn client_id(&self) -> u16 {
+        match self {
+            Transaction::Deposit(client_id, ..)
+            | Transaction::Withdrawal(client_id, ..)
+            | Transaction::Dispute(client_id, ..)
+            | Transaction::Resolve(client_id, ..)
+            | Transaction::Chargeback(client_id, ..)
+            | Transaction::Unlock(client_id, ..) => *client_id,
+        }
+    }
+
+    /// Returns the amount of the transaction, None for the operations without one
+    pub fn amount(&self) -> Option<M> {
+        match self {
+            Transaction::Deposit(.., amount) | Transaction::Withdrawal(.., amount) => Some(*amount),
+            Transaction::Dispute(.., amount) | Transaction::Chargeback(.., amount) => *amount,
+            Transaction::Resolve(..) | Transaction::Unlock(..) => None,
+        }
+    }
+
+    /// Returns the transaction ID, or the ID of the referenced transaction for the dispute operations
+    pub fn tx_id(&self) -> u32 {
+        match self {
+            Transaction::Deposit(_, tx_id, _)
+            | Transaction::Withdrawal(_, tx_id, _)
+            | Transaction::Dispute(_, tx_id, _)
+            | Transaction::Resolve(_, tx_id)
+            | Transaction::Chargeback(_, tx_id, _)
+            | Transaction::Unlock(_, tx_id) => *tx_id,
+        }
+    }
+}
+
+/// Writes the transaction as e.g. `deposit client=1 tx=10 amount=1.5000`, leaving out the
+/// amount of the operations without one.
+impl<M: Money> fmt::Display for Transaction<M> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{} client={} tx={}",
+            self.name(),
+            self.client_id(),
+            self.tx_id()
+        )?;
+        if let Some(amount) = self.amount() {
+            write!(f, " amount={amount}")?;
+        }
+        Ok(())
+    }
+}
+
+/// Writes the transaction as a record of the input format, with the `type`, `client`, `tx`
+/// and `amount` fields, the amount being empty (or null) for the operations without one.
+impl<M: Money + Serialize> Serialize for Transaction<M> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let mut record = serializer.serialize_struct("Transaction", 4)?;
+        record.serialize_field("type", self.name())?;
+        record.serialize_field("client", &self.client_id())?;
+        record.serialize_field("tx", &self.tx_id())?;
+        record.serialize_field("amount", &self.amount())?;
+        record.end()
+    }
+}
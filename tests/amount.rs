@@ -0,0 +1,88 @@
+use transact::{Amount, RoundingMode};
+
+fn amount(s: &str) -> Amount {
+    s.parse().unwrap()
+}
+
+#[test]
+fn negative_zero_is_written_without_sign() {
+    assert_eq!(amount("-0").to_string(), "0.0000");
+    assert_eq!(amount("-0.00001").to_string(), "0.0000");
+    assert_eq!(format!("{:.2}", amount("-0.0099")), "0.00");
+    assert_eq!(format!("{:.0}", amount("-0.5")), "0");
+    assert_eq!(format!("{:.2}", amount("-0.01")), "-0.01");
+}
+
+#[test]
+fn cancelling_operations_end_at_plain_zero() {
+    let balance = amount("0")
+        .checked_sub(amount("1.1"))
+        .and_then(|b| b.checked_add(amount("0.6")))
+        .and_then(|b| b.checked_add(amount("0.5")))
+        .unwrap();
+    assert_eq!(balance, Amount::default());
+    assert_eq!(balance.to_string(), "0.0000");
+}
+
+#[test]
+fn overflowing_operations_return_none() {
+    let max = amount("922337203685477.5807");
+    let min = amount("-922337203685477.5807");
+    assert_eq!(max.checked_add(amount("0.0001")), None);
+    assert_eq!(min.checked_sub(amount("0.0002")), None);
+    assert_eq!(
+        max.checked_sub(amount("0.0001")),
+        Some(amount("922337203685477.5806"))
+    );
+}
+
+#[test]
+fn rounding_modes_are_symmetric_for_negative_values() {
+    let cases = [
+        ("2.125", "2.12", "2.12", "2.13"),
+        ("2.135", "2.13", "2.14", "2.14"),
+        ("2.1251", "2.12", "2.13", "2.13"),
+        ("-2.125", "-2.12", "-2.12", "-2.13"),
+        ("-2.135", "-2.13", "-2.14", "-2.14"),
+    ];
+    for (value, trunc, half_even, half_up) in cases {
+        let value = amount(value);
+        let rounded = |mode| format!("{:.2}", value.round(2, mode));
+        assert_eq!(rounded(RoundingMode::Truncate), trunc);
+        assert_eq!(rounded(RoundingMode::HalfEven), half_even);
+        assert_eq!(rounded(RoundingMode::HalfUp), half_up);
+    }
+}
+
+#[test]
+fn float_conversion_truncates_symmetrically() {
+    let cases = [
+        (-0.00005, "0.0000"),
+        (0.00005, "0.0000"),
+        (1.0001, "1.0001"),
+        (-1.0001, "-1.0001"),
+        (0.00015, "0.0001"),
+        (-0.00015, "-0.0001"),
+        (2.5, "2.5000"),
+        (-2.5, "-2.5000"),
+        (900_000_000_000_000.0, "900000000000000.0000"),
+    ];
+    for (value, expected) in cases {
+        assert_eq!(Amount::try_from(value).unwrap().to_string(), expected);
+    }
+    assert!(!Amount::try_from(-0.00005).unwrap().is_negative());
+}
+
+#[test]
+fn float_conversion_rejects_out_of_range_values() {
+    for value in [
+        1e15,
+        -1e15,
+        1e300,
+        f64::INFINITY,
+        f64::NEG_INFINITY,
+        f64::NAN,
+    ] {
+        assert!(Amount::try_from(value).is_err(), "{value} was accepted");
+    }
+}
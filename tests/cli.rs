@@ -0,0 +1,863 @@
+use std::io::Write;
+use std::process::{Command, Output, Stdio};
+
+/// Runs the binary with the given arguments, feeding the input to its stdin.
+fn run(args: &[&str], input: &str) -> Output {
+    let mut child = Command::new(env!("CARGO_BIN_EXE_transact"))
+        .args(args)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .unwrap();
+    // The binary may exit before reading its input, e.g. on an invalid argument
+    if let Err(err) = child.stdin.take().unwrap().write_all(input.as_bytes()) {
+        assert_eq!(err.kind(), std::io::ErrorKind::BrokenPipe);
+    }
+    child.wait_with_output().unwrap()
+}
+
+#[test]
+fn accounts_are_sorted_by_client_id() {
+    let input = "type,client,tx,amount\n\
+                 deposit,30,1,1.0\n\
+                 deposit,2,2,1.0\n\
+                 deposit,65535,3,1.0\n\
+                 deposit,1,4,1.0\n";
+    let output = run(&[], input);
+    assert!(output.status.success());
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    let ids: Vec<&str> = stdout
+        .lines()
+        .skip(1)
+        .map(|line| line.split(',').next().unwrap())
+        .collect();
+    assert_eq!(ids, ["1", "2", "30", "65535"]);
+}
+
+#[test]
+fn rejected_transaction_sets_exit_code() {
+    let input = "type,client,tx,amount\n\
+                 deposit,1,1,1.0\n\
+                 withdrawal,1,2,5.0\n";
+    let output = run(&[], input);
+    assert_eq!(output.status.code(), Some(2));
+    assert!(String::from_utf8(output.stdout)
+        .unwrap()
+        .contains("1,1.0000,0.0000,1.0000,false"));
+
+    let output = run(&["--no-fail-on-reject"], input);
+    assert_eq!(output.status.code(), Some(0));
+}
+
+#[test]
+fn ndjson_input_matches_csv_input() {
+    let csv = "type,client,tx,amount\n\
+               deposit,1,1,2.5\n\
+               withdrawal,1,2,1.0\n\
+               dispute,1,1,\n";
+    let ndjson = "{\"type\":\"deposit\",\"client\":1,\"tx\":1,\"amount\":2.5}\n\
+                  \n\
+                  {\"type\":\"withdrawal\",\"client\":1,\"tx\":2,\"amount\":\"1.0\"}\n\
+                  {\"type\":\"dispute\",\"client\":1,\"tx\":1}\n";
+    let from_csv = run(&[], csv);
+    let from_ndjson = run(&["--input-format", "ndjson"], ndjson);
+    assert!(from_ndjson.status.success());
+    assert_eq!(from_csv.stdout, from_ndjson.stdout);
+}
+
+#[test]
+fn tx_range_skips_rows_outside_of_it() {
+    let input = "type,client,tx,amount\n\
+                 deposit,1,1,5.0\n\
+                 deposit,1,2,3.0\n\
+                 deposit,1,3,1.0\n\
+                 dispute,1,1,\n\
+                 dispute,1,2,\n";
+    let output = run(&["--min-tx", "2", "--max-tx", "2"], input);
+    assert!(output.status.success());
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    assert_eq!(stdout.lines().nth(1), Some("1,0.0000,3.0000,3.0000,false"));
+}
+
+#[test]
+fn headerless_input_is_read_by_position() {
+    let input = "deposit,1,1,5.0\n\
+                 withdrawal,1,2,2.0\n\
+                 dispute,1,1\n";
+    let output = run(&["--no-header"], input);
+    assert!(output.status.success());
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    assert_eq!(stdout.lines().nth(1), Some("1,-2.0000,5.0000,3.0000,false"));
+}
+
+#[test]
+fn byte_order_mark_is_ignored() {
+    let csv = "type,client,tx,amount\n\
+               deposit,1,1,2.5\n\
+               withdrawal,1,2,1.0\n";
+    let ndjson = "{\"type\":\"deposit\",\"client\":1,\"tx\":1,\"amount\":\"2.5\"}\n\
+                  {\"type\":\"withdrawal\",\"client\":1,\"tx\":2,\"amount\":\"1.0\"}\n";
+    for (args, input) in [(&[][..], csv), (&["--input-format", "ndjson"][..], ndjson)] {
+        let plain = run(args, input);
+        let with_bom = run(args, &format!("\u{feff}{input}"));
+        assert!(with_bom.status.success());
+        assert_eq!(plain.stdout, with_bom.stdout);
+    }
+}
+
+#[test]
+fn replay_of_recorded_transactions_matches_the_run() {
+    let record = std::env::temp_dir().join(format!("transact-record-{}.csv", std::process::id()));
+    let record_path = record.to_str().unwrap();
+    let input = "type,client,tx,amount\n\
+                 Deposit,1,1,2.123456\n\
+                 withdrawal,1,2,5.0\n\
+                 bogus,1,3,1.0\n\
+                 DISPUTE,1,1,\n";
+    let recorded = run(&["--record", record_path, "--quiet"], input);
+    let replayed = run(&["--replay", record_path, "--quiet"], "");
+    let log = std::fs::read_to_string(&record).unwrap();
+    std::fs::remove_file(&record).unwrap();
+    assert_eq!(recorded.stdout, replayed.stdout);
+    assert_eq!(
+        log,
+        "type,client,tx,amount,currency\n\
+         deposit,1,1,2.1234,\n\
+         withdrawal,1,2,5.0000,\n\
+         dispute,1,1,,\n"
+    );
+}
+
+#[test]
+fn reject_log_lists_the_rejected_transactions() {
+    let log = std::env::temp_dir().join(format!("transact-rejects-{}.csv", std::process::id()));
+    let input = "type,client,tx,amount\n\
+                 deposit,1,1,1.0\n\
+                 withdrawal,1,2,5.0\n\
+                 bogus,1,3,1.0\n\
+                 dispute,1,9,\n";
+    let output = run(&["--reject-log", log.to_str().unwrap(), "--quiet"], input);
+    let rejections = std::fs::read_to_string(&log).unwrap();
+    std::fs::remove_file(&log).unwrap();
+    assert_eq!(output.status.code(), Some(2));
+    assert_eq!(
+        rejections,
+        "type,client,tx,reason\n\
+         withdrawal,1,2,insufficient_funds\n\
+         bogus,1,3,unknown_operation\n\
+         dispute,1,9,unknown_transaction\n"
+    );
+}
+
+#[test]
+fn disputes_report_lists_the_open_disputes_in_order() {
+    let report = std::env::temp_dir().join(format!("transact-disputes-{}.csv", std::process::id()));
+    let report_path = report.to_str().unwrap();
+    let input = "type,client,tx,amount\n\
+                 deposit,2,1,4.0\n\
+                 deposit,1,3,2.0\n\
+                 deposit,1,2,1.5\n\
+                 withdrawal,1,4,0.5\n\
+                 deposit,1,5,1.0\n\
+                 dispute,1,4,\n\
+                 dispute,2,1,\n\
+                 dispute,1,3,\n\
+                 dispute,1,2,\n\
+                 dispute,1,5,\n\
+                 resolve,1,5,\n";
+    let output = run(&["--disputes-report", report_path], input);
+    let disputes = std::fs::read_to_string(&report).unwrap();
+    assert!(output.status.success());
+    assert_eq!(
+        disputes,
+        "client,tx,type,amount\n\
+         1,2,deposit,1.5000\n\
+         1,3,deposit,2.0000\n\
+         1,4,withdrawal,0.5000\n\
+         2,1,deposit,4.0000\n"
+    );
+
+    let input = "type,client,tx,amount,currency\n\
+                 deposit,1,1,2.0,\n\
+                 deposit,1,2,3.0,USD\n\
+                 dispute,1,2,,\n\
+                 dispute,1,1,,\n";
+    let output = run(&["--disputes-report", report_path], input);
+    let disputes = std::fs::read_to_string(&report).unwrap();
+    std::fs::remove_file(&report).unwrap();
+    assert!(output.status.success());
+    assert_eq!(
+        disputes,
+        "client,currency,tx,type,amount\n\
+         1,,1,deposit,2.0000\n\
+         1,USD,2,deposit,3.0000\n"
+    );
+}
+
+#[test]
+fn delimiter_applies_to_the_input_and_the_output() {
+    let input = "type;client;tx;amount\n\
+                 deposit;1;1;2.5\n\
+                 withdrawal;1;2;1.0\n";
+    let output = run(&["--delimiter", ";"], input);
+    assert!(output.status.success());
+    assert_eq!(
+        String::from_utf8(output.stdout).unwrap(),
+        "client;available;held;total;locked\n1;1.5000;0.0000;1.5000;false\n"
+    );
+
+    for delimiter in ["§", ";;", ""] {
+        let output = run(&["--delimiter", delimiter], "");
+        assert_eq!(output.status.code(), Some(1));
+        assert!(output.stdout.is_empty());
+        let stderr = String::from_utf8(output.stderr).unwrap();
+        assert!(stderr.contains("--delimiter must be a single ASCII character"));
+    }
+}
+
+#[test]
+fn closed_output_pipe_ends_the_run_quietly() {
+    use std::io::{BufRead, BufReader};
+
+    // Enough accounts to fill the pipe buffer after the reader is gone
+    let mut input = String::from("type,client,tx,amount\n");
+    for client in 1..=10_000 {
+        input.push_str(&format!("deposit,{client},{client},1.0\n"));
+    }
+    let mut child = Command::new(env!("CARGO_BIN_EXE_transact"))
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .unwrap();
+    child
+        .stdin
+        .take()
+        .unwrap()
+        .write_all(input.as_bytes())
+        .unwrap();
+    let mut header = String::new();
+    BufReader::new(child.stdout.take().unwrap())
+        .read_line(&mut header)
+        .unwrap();
+    assert_eq!(header, "client,available,held,total,locked\n");
+    let output = child.wait_with_output().unwrap();
+    assert_eq!(output.status.code(), Some(0));
+    let stderr = String::from_utf8(output.stderr).unwrap();
+    assert!(!stderr.contains("panicked"), "{stderr}");
+}
+
+#[test]
+fn precision_reduces_the_written_decimal_places() {
+    let input = "type,client,tx,amount\n\
+                 deposit,1,1,2.1259\n";
+    let written = |precision| {
+        let output = run(&["--precision", precision], input);
+        assert!(output.status.success());
+        String::from_utf8(output.stdout).unwrap()
+    };
+    assert_eq!(
+        written("4").lines().nth(1),
+        Some("1,2.1259,0.0000,2.1259,false")
+    );
+    assert_eq!(written("2").lines().nth(1), Some("1,2.12,0.00,2.12,false"));
+    assert_eq!(written("0").lines().nth(1), Some("1,2,0,2,false"));
+
+    let output = run(&["--precision", "5"], "");
+    assert_eq!(output.status.code(), Some(1));
+    assert!(output.stdout.is_empty());
+    let stderr = String::from_utf8(output.stderr).unwrap();
+    assert!(stderr.contains("--precision must be between 0 and 4"));
+}
+
+#[test]
+fn rounding_modes_apply_symmetrically_to_negative_balances() {
+    // The disputed deposits leave the available funds at -2.135 and -2.125
+    let input = "type,client,tx,amount\n\
+                 deposit,1,1,4.25\n\
+                 withdrawal,1,2,2.135\n\
+                 dispute,1,1,\n\
+                 deposit,2,3,4.25\n\
+                 withdrawal,2,4,2.125\n\
+                 dispute,2,3,\n";
+    let written = |mode| {
+        let output = run(&["--precision", "2", "--rounding", mode], input);
+        assert!(output.status.success());
+        String::from_utf8(output.stdout).unwrap()
+    };
+    assert_eq!(
+        written("trunc"),
+        "client,available,held,total,locked\n\
+         1,-2.13,4.25,2.11,false\n\
+         2,-2.12,4.25,2.12,false\n"
+    );
+    assert_eq!(
+        written("half-even"),
+        "client,available,held,total,locked\n\
+         1,-2.14,4.25,2.12,false\n\
+         2,-2.12,4.25,2.12,false\n"
+    );
+    assert_eq!(
+        written("half-up"),
+        "client,available,held,total,locked\n\
+         1,-2.14,4.25,2.12,false\n\
+         2,-2.13,4.25,2.13,false\n"
+    );
+}
+
+#[test]
+fn output_option_writes_the_accounts_to_the_file() {
+    let path = std::env::temp_dir().join(format!("transact-output-{}.csv", std::process::id()));
+    let input = "type,client,tx,amount\n\
+                 deposit,2,1,1.0\n\
+                 deposit,1,2,2.5\n";
+    let output = run(&["--output", path.to_str().unwrap()], input);
+    let written = std::fs::read_to_string(&path).unwrap();
+    std::fs::remove_file(&path).unwrap();
+    assert!(output.status.success());
+    assert!(output.stdout.is_empty());
+    assert_eq!(
+        written,
+        "client,available,held,total,locked\n\
+         1,2.5000,0.0000,2.5000,false\n\
+         2,1.0000,0.0000,1.0000,false\n"
+    );
+}
+
+#[test]
+fn stats_are_written_per_transaction_type() {
+    let input = "type,client,tx,amount\n\
+                 deposit,1,1,4.0\n\
+                 withdrawal,1,2,1.0\n\
+                 deposit,2,3,1.0\n\
+                 dispute,1,1,\n";
+    let output = run(&["--stats"], input);
+    assert!(output.status.success());
+    let stderr = String::from_utf8(output.stderr).unwrap();
+    let lines: Vec<_> = stderr.lines().collect();
+    assert_eq!(lines.len(), 4, "{stderr}");
+    assert!(lines[0].starts_with("Stats: 4 rows in "));
+    assert!(lines[0].ends_with(" rows/sec") || lines[0].ends_with(", rate unknown"));
+    let types = ["deposit: 2", "dispute: 1", "withdrawal: 1"];
+    for (line, counted) in lines[1..].iter().zip(types) {
+        assert!(line.starts_with(&format!("Stats: {counted} transactions, ")));
+        assert!(line.ends_with("ms in the engine"));
+    }
+}
+
+#[test]
+fn flushing_every_few_accounts_keeps_the_output() {
+    let mut input = String::from("type,client,tx,amount\n");
+    for client in 1..=5 {
+        input.push_str(&format!("deposit,{client},{client},1.5\n"));
+    }
+    let plain = run(&[], &input);
+    for interval in ["1", "2", "10"] {
+        let flushed = run(&["--flush-every", interval], &input);
+        assert!(flushed.status.success());
+        assert_eq!(flushed.stdout, plain.stdout);
+    }
+    assert_eq!(String::from_utf8(plain.stdout).unwrap().lines().count(), 6);
+
+    let output = run(&["--flush-every", "0"], "");
+    assert_eq!(output.status.code(), Some(1));
+    let stderr = String::from_utf8(output.stderr).unwrap();
+    assert!(stderr.contains("--flush-every must be at least 1"));
+}
+
+#[test]
+fn grouped_amounts_are_read_on_request() {
+    let input = "type,client,tx,amount\n\
+                 deposit,1,1,\"1,000.50\"\n";
+    let output = run(&[], input);
+    assert_eq!(output.status.code(), Some(2));
+    assert_eq!(
+        String::from_utf8(output.stdout).unwrap(),
+        "client,available,held,total,locked\n"
+    );
+    let stderr = String::from_utf8(output.stderr).unwrap();
+    assert!(stderr.contains("DEPOSIT #1 invalid amount '1,000.50'"));
+
+    let output = run(&["--locale-amounts"], input);
+    assert!(output.status.success());
+    assert_eq!(
+        String::from_utf8(output.stdout).unwrap(),
+        "client,available,held,total,locked\n1,1000.5000,0.0000,1000.5000,false\n"
+    );
+}
+
+#[test]
+fn saved_state_is_resumed_in_the_next_run() {
+    let state = std::env::temp_dir().join(format!("transact-state-{}.json", std::process::id()));
+    let state_path = state.to_str().unwrap();
+    let first = "type,client,tx,amount\n\
+                 deposit,1,1,5.0\n\
+                 withdrawal,1,2,1.0\n";
+    let output = run(&["--save-state", state_path], first);
+    assert!(output.status.success());
+
+    // The deposit of the first run can still be disputed
+    let second = "type,client,tx,amount\n\
+                  dispute,1,1,\n\
+                  deposit,2,3,2.0\n";
+    let output = run(&["--resume", state_path], second);
+    assert!(output.status.success());
+    assert_eq!(
+        String::from_utf8(output.stdout).unwrap(),
+        "client,available,held,total,locked\n\
+         1,-1.0000,5.0000,4.0000,false\n\
+         2,2.0000,0.0000,2.0000,false\n"
+    );
+
+    let snapshot = std::fs::read_to_string(&state).unwrap();
+    let tampered = snapshot.replace("\"total\":\"4.0000\"", "\"total\":\"4.5000\"");
+    assert_ne!(tampered, snapshot);
+    std::fs::write(&state, tampered).unwrap();
+    let output = run(&["--resume", state_path], second);
+    std::fs::remove_file(&state).unwrap();
+    assert_eq!(output.status.code(), Some(1));
+    assert!(output.stdout.is_empty());
+    let stderr = String::from_utf8(output.stderr).unwrap();
+    assert!(stderr.contains("inconsistent balances of clients 1"));
+}
+
+#[test]
+fn bad_rows_are_skipped_on_request() {
+    let input = "type,client,tx,amount\n\
+                 deposit,1,1,5.0\n\
+                 deposit,x,2,1.0\n\
+                 deposit,1,3,1.0\n";
+    assert_eq!(run(&[], input).status.code(), Some(1));
+    let output = run(&["--skip-bad-rows"], input);
+    assert_eq!(output.status.code(), Some(2));
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    assert_eq!(stdout.lines().nth(1), Some("1,6.0000,0.0000,6.0000,false"));
+    let stderr = String::from_utf8(output.stderr).unwrap();
+    assert!(stderr.contains("Skipped 1 malformed records"));
+}
+
+#[test]
+fn opening_balances_with_inconsistent_totals_are_refused() {
+    let opening = std::env::temp_dir().join(format!("transact-opening-{}.csv", std::process::id()));
+    std::fs::write(
+        &opening,
+        "client,available,held,total,locked\n\
+         1,1.0,0,1.0,false\n\
+         2,1.0,0.5,1.0,false\n\
+         4,0,0,2.0,false\n",
+    )
+    .unwrap();
+    let output = run(
+        &["--opening", opening.to_str().unwrap()],
+        "type,client,tx,amount\n",
+    );
+    std::fs::remove_file(&opening).unwrap();
+    assert_eq!(output.status.code(), Some(1));
+    assert!(output.stdout.is_empty());
+    let stderr = String::from_utf8(output.stderr).unwrap();
+    assert!(stderr.contains("inconsistent balances of clients 2, 4"));
+}
+
+#[test]
+fn bincode_output_reads_back_into_clients() {
+    let input = "type,client,tx,amount\n\
+                 deposit,2,1,1.23456\n\
+                 deposit,1,2,2.0\n\
+                 dispute,1,2,\n\
+                 deposit,3,3,0\n";
+    let output = run(&["--format", "bincode", "--nonzero"], input);
+    assert!(output.status.success());
+    let clients = transact::read_accounts_bincode(output.stdout.as_slice()).unwrap();
+    let summary: Vec<(u16, String, String)> = clients
+        .iter()
+        .map(|cl| (cl.id, cl.available.to_string(), cl.held.to_string()))
+        .collect();
+    assert_eq!(
+        summary,
+        [
+            (1, "0.0000".to_string(), "2.0000".to_string()),
+            (2, "1.2345".to_string(), "0.0000".to_string())
+        ]
+    );
+}
+
+#[test]
+fn summary_counts_locked_account_rejections_separately() {
+    let input = "type,client,tx,amount\n\
+                 deposit,1,1,1.0\n\
+                 dispute,1,1,\n\
+                 chargeback,1,1,\n\
+                 deposit,1,2,1.0\n\
+                 withdrawal,1,3,1.0\n\
+                 withdrawal,2,4,1.0\n";
+    let output = run(&["--summary", "--quiet"], input);
+    let stderr = String::from_utf8(output.stderr).unwrap();
+    assert!(
+        stderr.contains("3 transactions processed, 3 rejected (2 on locked accounts)"),
+        "{stderr}"
+    );
+}
+
+#[test]
+fn json_log_lists_rejection_fields() {
+    let input = "type,client,tx,amount\n\
+                 deposit,1,1,1.0\n\
+                 withdrawal,1,2,5.0\n";
+    let output = run(&["--log-format", "json"], input);
+    let stderr = String::from_utf8(output.stderr).unwrap();
+    let record: serde_json::Value = serde_json::from_str(stderr.trim()).unwrap();
+    assert_eq!(record["level"], "warn");
+    assert_eq!(record["op"], "withdrawal");
+    assert_eq!(record["tx"], 2);
+    assert_eq!(record["client"], 1);
+    assert_eq!(record["reason"], "insufficient_funds");
+}
+
+#[test]
+fn progress_ends_with_the_count_of_rows_read() {
+    let input = "type,client,tx,amount\n\
+                 deposit,1,1,1.0\n\
+                 deposit,1,2,1.0\n\
+                 withdrawal,1,3,0.5\n";
+    let output = run(&["--progress", "--max-tx", "2"], input);
+    assert!(output.status.success());
+    let stderr = String::from_utf8(output.stderr).unwrap();
+    assert!(stderr.ends_with("\rProcessed 3 rows\n"), "{stderr:?}");
+    assert!(run(&[], input).stderr.is_empty());
+}
+
+#[test]
+fn columns_are_written_in_the_listed_order() {
+    let input = "type,client,tx,amount\n\
+                 deposit,1,1,2.5\n";
+    let output = run(&["--columns", "total,client,tx_count"], input);
+    assert!(output.status.success());
+    assert_eq!(
+        String::from_utf8(output.stdout).unwrap(),
+        "total,client,tx_count\n2.5000,1,1\n"
+    );
+
+    let output = run(&["--columns", "client,pending"], input);
+    assert_eq!(output.status.code(), Some(1));
+}
+
+#[test]
+fn transactions_are_sorted_by_time_on_request() {
+    let input = "type,client,tx,amount,currency,timestamp\n\
+                 withdrawal,1,2,1.0,,2024-03-01T10:00:00Z\n\
+                 deposit,1,1,1.5,,2024-03-01T11:00:00+02:00\n";
+    let output = run(&["--no-fail-on-reject"], input);
+    assert!(String::from_utf8(output.stdout)
+        .unwrap()
+        .contains("1,1.5000,0.0000,1.5000,false"));
+
+    let output = run(&["--sort-by-time"], input);
+    assert!(output.status.success());
+    assert!(String::from_utf8(output.stdout)
+        .unwrap()
+        .contains("1,0.5000,0.0000,0.5000,false"));
+}
+
+#[test]
+fn capabilities_are_written_without_reading_the_input() {
+    // No input is fed, since the binary exits without reading it, closing its stdin
+    let output = run(&["--capabilities"], "");
+    assert!(output.status.success());
+    let capabilities: serde_json::Value = serde_json::from_slice(&output.stdout).unwrap();
+    assert_eq!(capabilities["amount_decimals"], 4);
+    assert_eq!(capabilities["max_client_id"], 65535);
+    assert_eq!(capabilities["operations"][0], "deposit");
+}
+
+#[test]
+fn merged_shards_sum_the_accounts_of_a_client() {
+    let shard = |index: usize, accounts: &str| {
+        let path = std::env::temp_dir().join(format!(
+            "transact-shard-{}-{}.csv",
+            std::process::id(),
+            index
+        ));
+        std::fs::write(
+            &path,
+            format!("client,available,held,total,locked\n{accounts}"),
+        )
+        .unwrap();
+        path
+    };
+    let shards = [
+        shard(0, "1,1.5,0,1.5,false\n2,1.0,1.0,2.0,true\n"),
+        shard(1, "1,2.0,0.5,2.5,false\n"),
+        shard(2, "2,1.0,0,1.0,false\n"),
+    ];
+    let paths: Vec<&str> = shards.iter().map(|path| path.to_str().unwrap()).collect();
+
+    let output = run(&["--merge", paths[0], paths[1]], "");
+    assert!(output.status.success());
+    assert_eq!(
+        String::from_utf8(output.stdout).unwrap(),
+        "client,available,held,total,locked\n\
+         1,3.5000,0.5000,4.0000,false\n\
+         2,1.0000,1.0000,2.0000,true\n"
+    );
+
+    // Client 2 is locked in one shard only
+    let output = run(&["--merge", paths[0], paths[2]], "");
+    assert_eq!(output.status.code(), Some(1));
+    let output = run(&["--merge", "--force", paths[0], paths[2]], "");
+    assert!(String::from_utf8(output.stdout)
+        .unwrap()
+        .contains("2,2.0000,1.0000,3.0000,true"));
+    for path in shards {
+        std::fs::remove_file(path).unwrap();
+    }
+}
+
+#[test]
+fn strict_schema_refuses_rows_not_matching_the_header() {
+    let input = "type,client,tx,amount\n\
+                 deposit,1,1,1.0\n\
+                 deposit,1,2,1.0,USD\n";
+    let output = run(&[], input);
+    assert_eq!(output.status.code(), Some(0));
+
+    let output = run(&["--strict-schema"], input);
+    assert_eq!(output.status.code(), Some(1));
+    assert!(String::from_utf8(output.stderr)
+        .unwrap()
+        .contains("5 fields instead of 4"));
+
+    let output = run(&["--strict-schema"], "type,client,tx,amount,fee\n");
+    assert_eq!(output.status.code(), Some(1));
+}
+
+#[test]
+fn changed_only_leaves_out_the_untouched_opening_accounts() {
+    let opening = std::env::temp_dir().join(format!("transact-changed-{}.csv", std::process::id()));
+    std::fs::write(
+        &opening,
+        "client,available,held,total,locked\n\
+         1,1.0,0,1.0,false\n\
+         2,1.0,0,1.0,false\n",
+    )
+    .unwrap();
+    let output = run(
+        &["--opening", opening.to_str().unwrap(), "--changed-only"],
+        "type,client,tx,amount\n\
+         deposit,2,1,2.0\n\
+         withdrawal,1,2,5.0\n\
+         deposit,3,3,1.0\n",
+    );
+    std::fs::remove_file(&opening).unwrap();
+    // The rejected withdrawal doesn't change the account of client 1
+    assert_eq!(
+        String::from_utf8(output.stdout).unwrap(),
+        "client,available,held,total,locked\n\
+         2,3.0000,0.0000,3.0000,false\n\
+         3,1.0000,0.0000,1.0000,false\n"
+    );
+}
+
+#[test]
+fn idempotent_mode_refuses_threads() {
+    // The arguments are refused before any input is read
+    let output = run(&["--idempotent", "--threads", "2"], "");
+    assert_eq!(output.status.code(), Some(1));
+    assert!(output.stdout.is_empty());
+    let stderr = String::from_utf8(output.stderr).unwrap();
+    assert!(stderr.contains("--idempotent can't be combined with --threads"));
+}
+
+#[test]
+fn dispute_window_refuses_threads() {
+    let output = run(&["--dispute-window", "10", "--threads", "2"], "");
+    assert_eq!(output.status.code(), Some(1));
+    let stderr = String::from_utf8(output.stderr).unwrap();
+    assert!(stderr.contains("--dispute-window can't be combined with --threads"));
+}
+
+#[test]
+fn dispute_window_refuses_late_resolution() {
+    let output = run(&["--dispute-window", "1", "--allow-late-resolution"], "");
+    assert_eq!(output.status.code(), Some(1));
+    assert!(output.stdout.is_empty());
+    let stderr = String::from_utf8(output.stderr).unwrap();
+    assert!(stderr.contains("--dispute-window can't be combined with --allow-late-resolution"));
+}
+
+#[test]
+fn changed_only_keeps_the_carried_fee_account_credited_with_fees() {
+    let opening = std::env::temp_dir().join(format!("transact-fees-{}.csv", std::process::id()));
+    std::fs::write(
+        &opening,
+        "client,available,held,total,locked\n\
+         1,5.0,0,5.0,false\n\
+         2,1.0,0,1.0,false\n\
+         9,1.0,0,1.0,false\n",
+    )
+    .unwrap();
+    let output = run(
+        &[
+            "--opening",
+            opening.to_str().unwrap(),
+            "--changed-only",
+            "--withdrawal-fee",
+            "0.5",
+            "--fee-client",
+            "9",
+        ],
+        "type,client,tx,amount\n\
+         withdrawal,1,1,2.0\n",
+    );
+    std::fs::remove_file(&opening).unwrap();
+    // The fee account has no transaction of its own, only the credited fee
+    assert_eq!(
+        String::from_utf8(output.stdout).unwrap(),
+        "client,available,held,total,locked\n\
+         1,2.5000,0.0000,2.5000,false\n\
+         9,1.5000,0.0000,1.5000,false\n"
+    );
+}
+
+#[test]
+fn threads_produce_the_same_accounts_as_a_single_thread() {
+    let root = std::path::Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures");
+    for fixture in std::fs::read_dir(&root).unwrap() {
+        let input = fixture.unwrap().path().join("input.csv");
+        let input = std::fs::read_to_string(&input).unwrap();
+        let single = run(&["--quiet", "--with-counts", "--threads", "1"], &input);
+        let sharded = run(&["--quiet", "--with-counts", "--threads", "4"], &input);
+        assert_eq!(single.status.code(), sharded.status.code());
+        assert_eq!(
+            String::from_utf8(single.stdout).unwrap(),
+            String::from_utf8(sharded.stdout).unwrap(),
+            "input:\n{input}"
+        );
+    }
+}
+
+#[test]
+fn gzip_input_matches_the_plain_csv() {
+    let plain = std::path::Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/test1.csv");
+    let compressed =
+        std::env::temp_dir().join(format!("transact-input-{}.csv.gz", std::process::id()));
+    let mut encoder = flate2::write::GzEncoder::new(
+        std::fs::File::create(&compressed).unwrap(),
+        flate2::Compression::default(),
+    );
+    encoder.write_all(&std::fs::read(&plain).unwrap()).unwrap();
+    encoder.finish().unwrap();
+    let expected = run(&[plain.to_str().unwrap()], "");
+    let output = run(&[compressed.to_str().unwrap()], "");
+    std::fs::remove_file(&compressed).unwrap();
+    assert_eq!(output.status.code(), expected.status.code());
+    assert!(!expected.stdout.is_empty());
+    assert_eq!(output.stdout, expected.stdout);
+}
+
+#[test]
+fn follow_mode_writes_snapshots_and_exits_at_the_end_of_a_regular_file() {
+    let input = std::env::temp_dir().join(format!("transact-follow-{}.csv", std::process::id()));
+    std::fs::write(
+        &input,
+        "type,client,tx,amount\n\
+         deposit,1,1,2.0\n\
+         snapshot,0,0,\n\
+         deposit,1,2,1.0\n",
+    )
+    .unwrap();
+    let output = run(&["--follow", input.to_str().unwrap()], "");
+    std::fs::remove_file(&input).unwrap();
+    assert_eq!(output.status.code(), Some(0));
+    assert_eq!(
+        String::from_utf8(output.stdout).unwrap(),
+        "client,available,held,total,locked\n\
+         1,2.0000,0.0000,2.0000,false\n\
+         client,available,held,total,locked\n\
+         1,3.0000,0.0000,3.0000,false\n"
+    );
+}
+
+#[test]
+fn validation_of_a_valid_input_writes_nothing() {
+    let output = run(
+        &["--validate"],
+        "type,client,tx,amount\n\
+         deposit,1,1,2.0\n\
+         dispute,1,1,\n\
+         resolve,1,1,\n",
+    );
+    assert_eq!(output.status.code(), Some(0));
+    assert!(output.stdout.is_empty());
+    let stderr = String::from_utf8(output.stderr).unwrap();
+    assert!(stderr.contains("Validation: 3 records checked, 0 problems found"));
+}
+
+#[test]
+fn validation_reports_the_problems_with_their_lines() {
+    let output = run(
+        &["--validate"],
+        "type,client,tx,amount\n\
+         deposit,1,1,2.0\n\
+         deposit,x,2,3.0\n\
+         dispute,2,1,\n",
+    );
+    assert_eq!(output.status.code(), Some(1));
+    assert!(output.stdout.is_empty());
+    let stderr = String::from_utf8(output.stderr).unwrap();
+    assert!(stderr.contains("Malformed record #2 at line 3"));
+    assert!(stderr.contains("DISPUTE #1 refers to a transaction of client 1"));
+    assert!(stderr.contains("Validation: 3 records checked, 2 problems found"));
+}
+
+#[test]
+fn strict_precision_rejects_the_digits_beyond_four_decimals() {
+    let input = "type,client,tx,amount\n\
+                 deposit,1,1,1.23456\n\
+                 deposit,1,2,1.2345\n\
+                 deposit,1,3,1.23450\n";
+    let output = run(&["--strict-precision", "--log-format", "json"], input);
+    assert_eq!(output.status.code(), Some(2));
+    assert_eq!(
+        String::from_utf8(output.stdout).unwrap(),
+        "client,available,held,total,locked\n1,1.2345,0.0000,1.2345,false\n"
+    );
+    let stderr = String::from_utf8(output.stderr).unwrap();
+    let rejected: Vec<serde_json::Value> = stderr
+        .lines()
+        .map(|line| serde_json::from_str(line).unwrap())
+        .collect();
+    // The trailing zero is a digit as written, so it's rejected as well
+    let reasons: Vec<_> = rejected.iter().map(|r| (&r["tx"], &r["reason"])).collect();
+    assert_eq!(
+        reasons,
+        [
+            (&1.into(), &"too_precise".into()),
+            (&3.into(), &"too_precise".into())
+        ]
+    );
+
+    // Without the option, the extra digits are truncated
+    let output = run(&[], input);
+    assert!(String::from_utf8(output.stdout)
+        .unwrap()
+        .contains("1,3.7035,0.0000,3.7035,false"));
+}
+
+#[test]
+fn threads_keep_a_reused_transaction_id_with_its_first_client() {
+    let input = "type,client,tx,amount\n\
+                 deposit,1,1,5\n\
+                 deposit,2,1,3\n\
+                 dispute,2,1,\n";
+    let single = run(&["--threads", "1"], input);
+    let sharded = run(&["--threads", "2"], input);
+    assert_eq!(single.status.code(), Some(2));
+    assert_eq!(sharded.status.code(), Some(2));
+    assert_eq!(
+        String::from_utf8(sharded.stdout).unwrap(),
+        String::from_utf8(single.stdout).unwrap()
+    );
+    let stderr = String::from_utf8(sharded.stderr).unwrap();
+    assert!(stderr.contains("DISPUTE #1 client mismatch exp:2 act:1"));
+}
@@ -0,0 +1,1139 @@
+use std::fmt;
+use transact::{
+    run_from_bytes, run_from_path, run_transactions, Amount, Client, Currency, EngineConfig,
+    EngineError, Money, Transaction, TransactionEngine, TransactionError,
+};
+
+fn amount(s: &str) -> Amount {
+    s.parse().unwrap()
+}
+
+fn account(engine: &TransactionEngine, client_id: u16) -> &Client {
+    engine.get_account(client_id).unwrap()
+}
+
+#[test]
+fn invariant_holds_through_dispute_and_resolve_sequence() {
+    let mut engine = TransactionEngine::new();
+    let transactions = vec![
+        Transaction::Deposit(1, 1, amount("10.0")),
+        Transaction::Deposit(1, 2, amount("5.5")),
+        Transaction::Withdrawal(1, 3, amount("12.25")),
+        Transaction::Dispute(1, 1, None),
+        Transaction::Dispute(1, 3, None),
+        Transaction::Resolve(1, 1),
+        Transaction::Dispute(1, 1, None),
+        Transaction::Resolve(1, 3),
+        Transaction::Resolve(1, 1),
+    ];
+    for tx in transactions {
+        engine.process(tx).unwrap();
+        assert!(account(&engine, 1).check_invariant());
+    }
+    let client = account(&engine, 1);
+    assert_eq!(client.available, amount("3.25"));
+    assert_eq!(client.held, amount("0"));
+    assert_eq!(client.total, amount("3.25"));
+    assert!(!client.locked);
+}
+
+#[test]
+fn resolved_withdrawal_dispute_keeps_the_withdrawal() {
+    let mut engine = TransactionEngine::new();
+    engine
+        .process(Transaction::Deposit(1, 1, amount("10.0")))
+        .unwrap();
+    engine
+        .process(Transaction::Withdrawal(1, 2, amount("4.0")))
+        .unwrap();
+    engine.process(Transaction::Dispute(1, 2, None)).unwrap();
+
+    let client = account(&engine, 1);
+    assert_eq!(client.available, amount("6.0"));
+    assert_eq!(client.held, amount("4.0"));
+    assert_eq!(client.total, amount("10.0"));
+    assert!(client.check_invariant());
+
+    engine.process(Transaction::Resolve(1, 2)).unwrap();
+    let client = account(&engine, 1);
+    assert_eq!(client.available, amount("6.0"));
+    assert_eq!(client.held, amount("0"));
+    assert_eq!(client.total, amount("6.0"));
+    assert!(!client.locked);
+}
+
+#[test]
+fn charged_back_withdrawal_dispute_returns_the_funds() {
+    let mut engine = TransactionEngine::new();
+    engine
+        .process(Transaction::Deposit(1, 1, amount("10.0")))
+        .unwrap();
+    engine
+        .process(Transaction::Withdrawal(1, 2, amount("4.0")))
+        .unwrap();
+    engine.process(Transaction::Dispute(1, 2, None)).unwrap();
+    engine.process(Transaction::Chargeback(1, 2, None)).unwrap();
+
+    let client = account(&engine, 1);
+    assert_eq!(client.available, amount("10.0"));
+    assert_eq!(client.held, amount("0"));
+    assert_eq!(client.total, amount("10.0"));
+    assert!(client.locked);
+    assert!(client.check_invariant());
+}
+
+#[test]
+fn overflowing_deposit_is_rejected_and_leaves_balance_unchanged() {
+    let mut engine = TransactionEngine::new();
+    engine
+        .process(Transaction::Deposit(1, 1, amount("900000000000000")))
+        .unwrap();
+    let result = engine.process(Transaction::Deposit(1, 2, amount("900000000000000")));
+    assert_eq!(result, Err(TransactionError::Overflow));
+
+    let client = account(&engine, 1);
+    assert_eq!(client.available, amount("900000000000000"));
+    assert_eq!(client.total, amount("900000000000000"));
+    assert!(client.check_invariant());
+}
+
+#[test]
+fn accounts_iterate_in_client_id_order() {
+    let mut engine = TransactionEngine::new();
+    for (tx_id, client_id) in [(1, 7), (2, 3), (3, 65535), (4, 0)] {
+        engine
+            .process(Transaction::Deposit(client_id, tx_id, amount("1.0")))
+            .unwrap();
+    }
+    let ids: Vec<u16> = engine.accounts().map(|client| client.id).collect();
+    assert_eq!(ids, [0, 3, 7, 65535]);
+}
+
+#[test]
+fn dispute_from_another_client_leaves_the_transaction_disputable() {
+    let mut engine = TransactionEngine::new();
+    engine
+        .process(Transaction::Deposit(1, 1, amount("2.0")))
+        .unwrap();
+    engine
+        .process(Transaction::Deposit(2, 2, amount("3.0")))
+        .unwrap();
+    let result = engine.process(Transaction::Dispute(2, 1, None));
+    assert_eq!(
+        result,
+        Err(TransactionError::ClientMismatch {
+            expected: 2,
+            actual: 1
+        })
+    );
+
+    engine.process(Transaction::Dispute(1, 1, None)).unwrap();
+    assert_eq!(account(&engine, 1).held, amount("2.0"));
+    assert_eq!(account(&engine, 2).held, amount("0"));
+}
+
+#[test]
+fn currencies_are_kept_in_separate_accounts() {
+    let usd: Currency = "usd".parse().unwrap();
+    let eur: Currency = "EUR".parse().unwrap();
+    let mut engine = TransactionEngine::new();
+    engine
+        .process_in(Transaction::Deposit(1, 1, amount("5.0")), Some(usd))
+        .unwrap();
+    engine
+        .process_in(Transaction::Deposit(1, 2, amount("2.0")), Some(eur))
+        .unwrap();
+    let result = engine.process_in(Transaction::Withdrawal(1, 3, amount("3.0")), Some(eur));
+    assert!(matches!(
+        result,
+        Err(TransactionError::InsufficientFunds { .. })
+    ));
+    // The dispute follows the currency of the referenced deposit
+    engine.process(Transaction::Dispute(1, 1, None)).unwrap();
+
+    let usd_account = engine.get_account_in(1, Some(usd)).unwrap();
+    assert_eq!(usd_account.available, amount("0"));
+    assert_eq!(usd_account.held, amount("5.0"));
+    let eur_account = engine.get_account_in(1, Some(eur)).unwrap();
+    assert_eq!(eur_account.available, amount("2.0"));
+    assert!(engine.get_account(1).is_none());
+}
+
+#[test]
+fn partial_chargeback_reverses_only_its_amount() {
+    let mut engine = TransactionEngine::new();
+    engine
+        .process(Transaction::Deposit(1, 1, amount("10.0")))
+        .unwrap();
+    engine
+        .process(Transaction::Dispute(1, 1, Some(amount("6.0"))))
+        .unwrap();
+    assert_eq!(account(&engine, 1).available, amount("4.0"));
+    assert_eq!(account(&engine, 1).held, amount("6.0"));
+
+    let result = engine.process(Transaction::Chargeback(1, 1, Some(amount("7.0"))));
+    assert_eq!(
+        result,
+        Err(TransactionError::AmountTooLarge {
+            limit: amount("6.0"),
+            requested: amount("7.0")
+        })
+    );
+    engine
+        .process(Transaction::Chargeback(1, 1, Some(amount("2.5"))))
+        .unwrap();
+
+    let client = account(&engine, 1);
+    assert_eq!(client.available, amount("7.5"));
+    assert_eq!(client.held, amount("0"));
+    assert_eq!(client.total, amount("7.5"));
+    assert!(client.locked);
+}
+
+#[test]
+fn partial_dispute_cannot_exceed_the_transaction() {
+    let mut engine = TransactionEngine::new();
+    engine
+        .process(Transaction::Deposit(1, 1, amount("1.0")))
+        .unwrap();
+    let result = engine.process(Transaction::Dispute(1, 1, Some(amount("1.5"))));
+    assert!(matches!(
+        result,
+        Err(TransactionError::AmountTooLarge { .. })
+    ));
+    // The transaction can still be disputed in full
+    engine.process(Transaction::Dispute(1, 1, None)).unwrap();
+    assert_eq!(account(&engine, 1).held, amount("1.0"));
+}
+
+#[test]
+fn chargeback_of_spent_deposit_locks_with_negative_funds() {
+    let mut engine = TransactionEngine::new();
+    engine
+        .process(Transaction::Deposit(1, 1, amount("10.0")))
+        .unwrap();
+    engine
+        .process(Transaction::Withdrawal(1, 2, amount("10.0")))
+        .unwrap();
+    engine.process(Transaction::Dispute(1, 1, None)).unwrap();
+    engine.process(Transaction::Chargeback(1, 1, None)).unwrap();
+
+    let client = account(&engine, 1);
+    assert_eq!(client.available, amount("-10.0"));
+    assert_eq!(client.held, amount("0"));
+    assert_eq!(client.total, amount("-10.0"));
+    assert!(client.locked);
+    assert!(client.check_invariant());
+}
+
+#[test]
+fn dispute_of_withdrawn_deposit_cannot_be_used_to_keep_the_funds() {
+    let mut engine = TransactionEngine::new();
+    engine
+        .process(Transaction::Deposit(1, 1, amount("100.0")))
+        .unwrap();
+    engine
+        .process(Transaction::Withdrawal(1, 2, amount("100.0")))
+        .unwrap();
+    // The dispute holds the whole deposit, even though it was withdrawn already
+    engine.process(Transaction::Dispute(1, 1, None)).unwrap();
+    let disputed = account(&engine, 1);
+    assert_eq!(disputed.available, amount("-100.0"));
+    assert_eq!(disputed.held, amount("100.0"));
+    assert_eq!(disputed.total, amount("0"));
+    assert!(matches!(
+        engine.process(Transaction::Withdrawal(1, 3, amount("1.0"))),
+        Err(TransactionError::InsufficientFunds { .. })
+    ));
+
+    engine.process(Transaction::Chargeback(1, 1, None)).unwrap();
+    assert_eq!(
+        account(&engine, 1),
+        &peaked(client(1, "-100.0", "0", true, 4), "100.0")
+    );
+    assert_eq!(
+        engine.process(Transaction::Deposit(1, 4, amount("100.0"))),
+        Err(TransactionError::AccountLocked(1))
+    );
+}
+
+#[test]
+fn repeated_deposit_id_keeps_the_first_transaction_for_disputes() {
+    let mut engine = TransactionEngine::new();
+    engine
+        .process(Transaction::Deposit(1, 1, amount("2.0")))
+        .unwrap();
+    engine
+        .process(Transaction::Deposit(1, 1, amount("5.0")))
+        .unwrap();
+    engine.process(Transaction::Dispute(1, 1, None)).unwrap();
+    let client = account(&engine, 1);
+    assert_eq!(client.available, amount("5.0"));
+    assert_eq!(client.held, amount("2.0"));
+    // Once settled, the first transaction isn't replaced by the repeated one
+    engine.process(Transaction::Resolve(1, 1)).unwrap();
+    engine.process(Transaction::Dispute(1, 1, None)).unwrap();
+    assert_eq!(account(&engine, 1).held, amount("2.0"));
+}
+
+#[test]
+fn only_deposits_and_withdrawals_can_be_disputed() {
+    let mut engine = TransactionEngine::new();
+    engine
+        .process(Transaction::Deposit(1, 1, amount("2.0")))
+        .unwrap();
+    engine.process(Transaction::Dispute(1, 1, None)).unwrap();
+    engine.process(Transaction::Resolve(1, 1)).unwrap();
+    // The disputes and resolutions don't register their IDs as transactions
+    for tx_id in [2, 3] {
+        assert_eq!(
+            engine.process(Transaction::Dispute(1, tx_id, None)),
+            Err(TransactionError::UnknownTransaction)
+        );
+        assert_eq!(
+            engine.process(Transaction::Resolve(1, tx_id)),
+            Err(TransactionError::UnknownTransaction)
+        );
+    }
+    assert_eq!(account(&engine, 1).available, amount("2.0"));
+}
+
+#[test]
+fn zero_amount_deposit_goes_through_dispute_resolve_and_chargeback() {
+    let mut engine = TransactionEngine::new();
+    engine
+        .process(Transaction::Deposit(1, 1, amount("0.0")))
+        .unwrap();
+    engine.process(Transaction::Dispute(1, 1, None)).unwrap();
+    assert_eq!(
+        engine.process(Transaction::Dispute(1, 1, None)),
+        Err(TransactionError::AlreadyDisputed)
+    );
+    engine.process(Transaction::Resolve(1, 1)).unwrap();
+    engine.process(Transaction::Dispute(1, 1, None)).unwrap();
+    engine.process(Transaction::Chargeback(1, 1, None)).unwrap();
+    assert_eq!(
+        engine.accounts().collect::<Vec<_>>(),
+        [&client(1, "0", "0", true, 5)]
+    );
+}
+
+#[test]
+fn stale_resolve_after_chargeback_is_rejected() {
+    let mut engine = TransactionEngine::new();
+    engine
+        .process(Transaction::Deposit(1, 1, amount("3.0")))
+        .unwrap();
+    engine.process(Transaction::Dispute(1, 1, None)).unwrap();
+    engine.process(Transaction::Chargeback(1, 1, None)).unwrap();
+    let result = engine.process(Transaction::Resolve(1, 1));
+    assert_eq!(result, Err(TransactionError::AlreadyChargedBack));
+
+    let client = account(&engine, 1);
+    assert_eq!(client.held, amount("0"));
+    assert_eq!(client.total, amount("0"));
+}
+
+#[test]
+fn resolve_with_inconsistent_held_funds_leaves_client_untouched() {
+    // A snapshot where the held funds don't cover the open dispute, as if it was tampered with
+    let snapshot = r#"{
+        "clients": {"1": {"client": 1, "available": "1.0", "held": "0.5",
+                          "total": "1.5", "locked": false}},
+        "transactions": {"1": {"client_id": 1, "amount": "2.0",
+                               "is_withdrawal": false, "state": "Disputed"}}
+    }"#;
+    let path = std::env::temp_dir().join(format!("transact-held-{}.json", std::process::id()));
+    std::fs::write(&path, snapshot).unwrap();
+    let mut engine = TransactionEngine::load_state(&path).unwrap();
+    std::fs::remove_file(&path).unwrap();
+
+    let result = engine.process(Transaction::Resolve(1, 1));
+    assert_eq!(
+        result,
+        Err(TransactionError::InconsistentHeld {
+            held: amount("0.5"),
+            required: amount("2.0")
+        })
+    );
+    let client = account(&engine, 1);
+    assert_eq!(client.available, amount("1.0"));
+    assert_eq!(client.held, amount("0.5"));
+    assert_eq!(client.total, amount("1.5"));
+}
+
+#[test]
+fn state_snapshot_round_trip_keeps_locked_and_held() {
+    let mut engine = TransactionEngine::new();
+    let transactions = vec![
+        Transaction::Deposit(1, 1, amount("4.0")),
+        Transaction::Dispute(1, 1, None),
+        Transaction::Deposit(2, 2, amount("1.5")),
+        Transaction::Dispute(2, 2, None),
+        Transaction::Chargeback(2, 2, None),
+    ];
+    for tx in transactions {
+        engine.process(tx).unwrap();
+    }
+    let path = std::env::temp_dir().join(format!("transact-state-{}.json", std::process::id()));
+    engine.save_state(&path).unwrap();
+    let mut resumed = TransactionEngine::load_state(&path).unwrap();
+    std::fs::remove_file(&path).unwrap();
+
+    assert_eq!(account(&resumed, 1).held, amount("4.0"));
+    assert!(account(&resumed, 2).locked);
+    // The outstanding dispute can still be settled after the reload
+    resumed.process(Transaction::Resolve(1, 1)).unwrap();
+    assert_eq!(account(&resumed, 1).available, amount("4.0"));
+    assert_eq!(account(&resumed, 1).held, amount("0"));
+}
+
+#[test]
+fn snapshot_with_inconsistent_balances_is_refused() {
+    let snapshot = r#"{
+        "clients": {"1": {"client": 1, "available": "1.0", "held": "0.5", "total": "1.0",
+                          "locked": false},
+                    "2": {"client": 2, "available": "1.0", "held": "0", "total": "1.0",
+                          "locked": false},
+                    "3/USD": {"client": 3, "currency": "USD", "available": "2.0",
+                              "held": "0", "total": "1.0", "locked": false}},
+        "transactions": {}
+    }"#;
+    let path = std::env::temp_dir().join(format!("transact-ledger-{}.json", std::process::id()));
+    std::fs::write(&path, snapshot).unwrap();
+    let err = TransactionEngine::<Amount>::load_state(&path).unwrap_err();
+    std::fs::remove_file(&path).unwrap();
+    assert_eq!(err.kind(), std::io::ErrorKind::InvalidData);
+    assert_eq!(err.to_string(), "inconsistent balances of clients 1, 3");
+}
+
+#[test]
+fn outstanding_dispute_of_locked_account_is_settled_only_if_configured() {
+    let transactions = [
+        Transaction::Deposit(1, 1, amount("5.0")),
+        Transaction::Deposit(1, 2, amount("3.0")),
+        Transaction::Dispute(1, 1, None),
+        Transaction::Dispute(1, 2, None),
+        Transaction::Chargeback(1, 2, None),
+    ];
+    let mut engine = TransactionEngine::new();
+    for tx in transactions.clone() {
+        engine.process(tx).unwrap();
+    }
+    assert_eq!(
+        engine.process(Transaction::Resolve(1, 1)),
+        Err(TransactionError::AccountLocked(1))
+    );
+    assert_eq!(account(&engine, 1).held, amount("5.0"));
+
+    let config = EngineConfig::new().settle_locked_accounts(true);
+    let mut engine = TransactionEngine::with_config(config);
+    for tx in transactions {
+        engine.process(tx).unwrap();
+    }
+    engine.process(Transaction::Resolve(1, 1)).unwrap();
+    assert_eq!(
+        account(&engine, 1),
+        &peaked(client(1, "5.0", "0", true, 6), "8.0")
+    );
+    // The settled transaction can be disputed again, while new funds are still blocked
+    engine.process(Transaction::Dispute(1, 1, None)).unwrap();
+    assert_eq!(
+        engine.process(Transaction::Deposit(1, 3, amount("1.0"))),
+        Err(TransactionError::AccountLocked(1))
+    );
+    assert_eq!(
+        engine.process(Transaction::Withdrawal(1, 4, amount("1.0"))),
+        Err(TransactionError::AccountLocked(1))
+    );
+    assert_eq!(
+        account(&engine, 1),
+        &peaked(client(1, "0", "5.0", true, 7), "8.0")
+    );
+}
+
+#[test]
+fn unlocked_account_accepts_new_deposits() {
+    let transactions = [
+        Transaction::Deposit(1, 1, amount("5.0")),
+        Transaction::Deposit(1, 2, amount("3.0")),
+        Transaction::Dispute(1, 2, None),
+        Transaction::Chargeback(1, 2, None),
+    ];
+    let mut engine = TransactionEngine::new();
+    for tx in transactions.clone() {
+        engine.process(tx).unwrap();
+    }
+    assert_eq!(
+        engine.process(Transaction::Unlock(1, 3)),
+        Err(TransactionError::UnlockNotAllowed)
+    );
+
+    let mut engine = TransactionEngine::with_config(EngineConfig::new().allow_unlock(true));
+    for tx in transactions {
+        engine.process(tx).unwrap();
+    }
+    assert_eq!(
+        engine.process(Transaction::Dispute(1, 1, None)),
+        Err(TransactionError::AccountLocked(1))
+    );
+    engine.process(Transaction::Unlock(1, 3)).unwrap();
+    assert_eq!(
+        engine.process(Transaction::Unlock(1, 4)),
+        Err(TransactionError::AccountNotLocked(1))
+    );
+    assert_eq!(
+        engine.process(Transaction::Unlock(2, 5)),
+        Err(TransactionError::UnknownClient(2))
+    );
+    engine
+        .process(Transaction::Deposit(1, 6, amount("2.0")))
+        .unwrap();
+    assert_eq!(
+        account(&engine, 1),
+        &peaked(client(1, "7.0", "0", false, 6), "3.0")
+    );
+    // The charge back stays final
+    assert_eq!(
+        engine.process(Transaction::Dispute(1, 2, None)),
+        Err(TransactionError::AlreadyChargedBack)
+    );
+    // The deposit whose dispute was rejected by the locked account can be disputed now
+    engine.process(Transaction::Dispute(1, 1, None)).unwrap();
+    assert_eq!(account(&engine, 1).held, amount("5.0"));
+}
+
+/// Builds the expected record of a client in the default currency
+fn client(id: u16, available: &str, held: &str, locked: bool, tx_count: u64) -> Client {
+    let (available, held) = (amount(available), amount(held));
+    Client {
+        id,
+        currency: None,
+        available,
+        held,
+        total: available.checked_add(held).unwrap(),
+        locked,
+        tx_count,
+        peak_held: held,
+    }
+}
+
+/// Sets the peak of the held funds, reached by a dispute settled later
+fn peaked(client: Client, peak_held: &str) -> Client {
+    Client {
+        peak_held: amount(peak_held),
+        ..client
+    }
+}
+
+#[test]
+fn run_transactions_skips_rejected_withdrawal() {
+    let clients = run_transactions(vec![
+        Transaction::Deposit(1, 1, amount("5.0")),
+        Transaction::Withdrawal(1, 2, amount("7.0")),
+        Transaction::Withdrawal(1, 3, amount("2.0")),
+        Transaction::Deposit(2, 4, amount("1.0")),
+    ]);
+    assert_eq!(
+        clients,
+        [
+            client(1, "3.0", "0", false, 2),
+            client(2, "1.0", "0", false, 1)
+        ]
+    );
+}
+
+#[test]
+fn run_from_path_returns_the_accounts_of_the_file() {
+    let clients = run_from_path(
+        "tests/fixtures/locked_account/input.csv",
+        EngineConfig::new(),
+    )
+    .unwrap();
+    let balances: Vec<(u16, Amount, Amount, bool)> = clients
+        .iter()
+        .map(|client| (client.id, client.available, client.total, client.locked))
+        .collect();
+    assert_eq!(
+        balances,
+        [
+            (3, amount("4.0"), amount("4.0"), true),
+            (4, amount("2.5"), amount("2.5"), false)
+        ]
+    );
+    assert!(matches!(
+        run_from_path("tests/fixtures/missing.csv", EngineConfig::new()),
+        Err(EngineError::Io(_))
+    ));
+    assert!(matches!(
+        run_from_bytes(
+            b"type,client,tx,amount\ndeposit,x,1,1.0\n",
+            EngineConfig::new()
+        ),
+        Err(EngineError::Malformed(_))
+    ));
+}
+
+#[test]
+fn run_transactions_held_funds_cannot_be_withdrawn_until_resolved() {
+    let clients = run_transactions(vec![
+        Transaction::Deposit(1, 1, amount("4.0")),
+        Transaction::Deposit(1, 2, amount("1.0")),
+        Transaction::Dispute(1, 1, None),
+        Transaction::Withdrawal(1, 3, amount("2.0")),
+        Transaction::Resolve(1, 1),
+        Transaction::Withdrawal(1, 4, amount("2.0")),
+    ]);
+    assert_eq!(clients, [peaked(client(1, "3.0", "0", false, 5), "4.0")]);
+}
+
+#[test]
+fn run_transactions_chargeback_locks_against_later_operations() {
+    let clients = run_transactions(vec![
+        Transaction::Deposit(1, 1, amount("4.0")),
+        Transaction::Deposit(1, 2, amount("3.0")),
+        Transaction::Dispute(1, 2, None),
+        Transaction::Chargeback(1, 2, None),
+        Transaction::Deposit(1, 3, amount("10.0")),
+        Transaction::Withdrawal(1, 4, amount("1.0")),
+        Transaction::Dispute(1, 1, None),
+    ]);
+    assert_eq!(clients, [peaked(client(1, "4.0", "0", true, 4), "3.0")]);
+}
+
+#[test]
+fn run_transactions_redispute_after_resolve_and_charge_back() {
+    let clients = run_transactions(vec![
+        Transaction::Deposit(1, 1, amount("6.0")),
+        Transaction::Dispute(1, 1, None),
+        Transaction::Resolve(1, 1),
+        Transaction::Resolve(1, 1),
+        Transaction::Dispute(1, 1, None),
+        Transaction::Chargeback(1, 1, None),
+        Transaction::Chargeback(1, 1, None),
+    ]);
+    assert_eq!(clients, [peaked(client(1, "0", "0", true, 5), "6.0")]);
+}
+
+#[test]
+fn run_transactions_disputes_interleaved_across_clients() {
+    let clients = run_transactions(vec![
+        Transaction::Deposit(1, 1, amount("2.0")),
+        Transaction::Deposit(2, 2, amount("3.0")),
+        Transaction::Dispute(1, 1, None),
+        Transaction::Dispute(2, 1, None),
+        Transaction::Dispute(2, 2, None),
+        Transaction::Chargeback(1, 1, None),
+        Transaction::Resolve(2, 2),
+    ]);
+    assert_eq!(
+        clients,
+        [
+            peaked(client(1, "0", "0", true, 3), "2.0"),
+            peaked(client(2, "3.0", "0", false, 3), "3.0")
+        ]
+    );
+}
+
+#[test]
+fn overdraft_allows_negative_funds_down_to_the_limit() {
+    let mut engine = TransactionEngine::with_config(EngineConfig::new().overdraft(amount("5.0")));
+    engine
+        .process(Transaction::Deposit(1, 1, amount("2.0")))
+        .unwrap();
+    engine
+        .process(Transaction::Withdrawal(1, 2, amount("6.0")))
+        .unwrap();
+    assert_eq!(
+        engine.process(Transaction::Withdrawal(1, 3, amount("1.5"))),
+        Err(TransactionError::InsufficientFunds {
+            available: amount("-4.0"),
+            required: amount("1.5"),
+        })
+    );
+    engine
+        .process(Transaction::Withdrawal(1, 4, amount("1.0")))
+        .unwrap();
+    let client = account(&engine, 1);
+    assert_eq!(client.available, amount("-5.0"));
+    assert_eq!(client.total, amount("-5.0"));
+}
+
+#[test]
+fn withdrawal_fee_is_credited_to_the_fee_client_but_not_disputed() {
+    let config = EngineConfig::new()
+        .withdrawal_fee(Some(amount("0.5")))
+        .fee_client(9);
+    let mut engine = TransactionEngine::with_config(config);
+    engine
+        .process(Transaction::Deposit(1, 1, amount("10.0")))
+        .unwrap();
+    engine
+        .process(Transaction::Withdrawal(1, 2, amount("4.0")))
+        .unwrap();
+    // The fee counts in the funds check
+    assert_eq!(
+        engine.process(Transaction::Withdrawal(1, 3, amount("5.3"))),
+        Err(TransactionError::InsufficientFunds {
+            available: amount("5.5"),
+            required: amount("5.8"),
+        })
+    );
+    // The dispute only holds the withdrawn amount, the fee stays charged
+    engine.process(Transaction::Dispute(1, 2, None)).unwrap();
+    engine.process(Transaction::Chargeback(1, 2, None)).unwrap();
+    let client = account(&engine, 1);
+    assert_eq!(client.available, amount("9.5"));
+    assert_eq!(client.total, amount("9.5"));
+    let fees = account(&engine, 9);
+    assert_eq!(fees.available, amount("0.5"));
+    assert_eq!(fees.total, amount("0.5"));
+    // The withdrawals of the fee client are free of the fee
+    engine
+        .process(Transaction::Withdrawal(9, 4, amount("0.5")))
+        .unwrap();
+    assert_eq!(account(&engine, 9).total, amount("0"));
+}
+
+#[test]
+fn deposit_beyond_the_client_total_limit_is_rejected() {
+    let config = EngineConfig::new().max_client_total(Some(amount("10.0")));
+    let mut engine = TransactionEngine::with_config(config);
+    engine
+        .process(Transaction::Deposit(1, 1, amount("8.0")))
+        .unwrap();
+    assert_eq!(
+        engine.process(Transaction::Deposit(1, 2, amount("2.5"))),
+        Err(TransactionError::LimitExceeded {
+            limit: amount("10.0"),
+            total: amount("10.5"),
+        })
+    );
+    // A rejected first deposit doesn't open the account
+    assert_eq!(
+        engine.process(Transaction::Deposit(2, 3, amount("11.0"))),
+        Err(TransactionError::LimitExceeded {
+            limit: amount("10.0"),
+            total: amount("11.0"),
+        })
+    );
+    assert!(engine.get_account(2).is_none());
+    engine
+        .process(Transaction::Deposit(1, 4, amount("2.0")))
+        .unwrap();
+    // The funds moved by a dispute stay within the account, so they are not limited
+    engine.process(Transaction::Dispute(1, 4, None)).unwrap();
+    engine.process(Transaction::Resolve(1, 4)).unwrap();
+    assert_eq!(account(&engine, 1).total, amount("10.0"));
+}
+
+#[test]
+fn max_clients_rejects_only_new_accounts() {
+    let mut engine = TransactionEngine::with_config(EngineConfig::new().max_clients(Some(2)));
+    engine
+        .process(Transaction::Deposit(1, 1, amount("1.0")))
+        .unwrap();
+    engine
+        .process(Transaction::Deposit(2, 2, amount("1.0")))
+        .unwrap();
+    assert_eq!(
+        engine.process(Transaction::Deposit(3, 3, amount("1.0"))),
+        Err(TransactionError::TooManyClients { limit: 2 })
+    );
+    engine
+        .process(Transaction::Deposit(1, 4, amount("2.0")))
+        .unwrap();
+    assert!(engine.get_account(3).is_none());
+    assert_eq!(account(&engine, 1).available, amount("3.0"));
+    // The rejected deposit isn't registered, so it can't be disputed later
+    assert_eq!(
+        engine.process(Transaction::Dispute(1, 3, None)),
+        Err(TransactionError::UnknownTransaction)
+    );
+}
+
+#[test]
+fn withdrawal_creates_unknown_client_only_if_configured() {
+    let mut engine = TransactionEngine::new();
+    assert_eq!(
+        engine.process(Transaction::Withdrawal(1, 1, amount("1.0"))),
+        Err(TransactionError::UnknownClient(1))
+    );
+    assert!(engine.get_account(1).is_none());
+
+    let mut engine = TransactionEngine::with_config(EngineConfig::new().create_on_withdrawal(true));
+    assert_eq!(
+        engine.process(Transaction::Withdrawal(1, 1, amount("1.0"))),
+        Err(TransactionError::InsufficientFunds {
+            available: amount("0"),
+            required: amount("1.0"),
+        })
+    );
+    assert_eq!(account(&engine, 1), &Client::new(1, None));
+    // The rejected withdrawal isn't registered, so it can't be disputed later
+    assert_eq!(
+        engine.process(Transaction::Dispute(1, 1, None)),
+        Err(TransactionError::UnknownTransaction)
+    );
+}
+
+#[test]
+fn early_dispute_is_applied_once_the_deposit_arrives() {
+    let config = EngineConfig::new().defer_early_disputes(true);
+    let mut engine = TransactionEngine::with_config(config);
+    engine
+        .process(Transaction::Deposit(1, 1, amount("1.0")))
+        .unwrap();
+    engine.process(Transaction::Dispute(1, 2, None)).unwrap();
+    assert_eq!(
+        engine.process(Transaction::Dispute(1, 2, None)),
+        Err(TransactionError::AlreadyDisputed)
+    );
+    // The parked dispute is only counted once applied
+    let client = account(&engine, 1);
+    assert_eq!(client.held, amount("0"));
+    assert_eq!(client.tx_count, 1);
+    engine
+        .process(Transaction::Deposit(1, 2, amount("4.0")))
+        .unwrap();
+    let client = account(&engine, 1);
+    assert_eq!(client.available, amount("1.0"));
+    assert_eq!(client.held, amount("4.0"));
+    assert_eq!(client.tx_count, 3);
+    engine.process(Transaction::Resolve(1, 2)).unwrap();
+    let client = account(&engine, 1);
+    assert_eq!(client.available, amount("5.0"));
+    assert_eq!(client.tx_count, 4);
+}
+
+#[test]
+fn only_deposits_create_client_accounts() {
+    let mut engine = TransactionEngine::new();
+    let rejected = [
+        Transaction::Withdrawal(1, 1, amount("1.0")),
+        Transaction::Dispute(1, 1, None),
+        Transaction::Resolve(1, 1),
+        Transaction::Chargeback(1, 1, None),
+    ];
+    for tx in rejected {
+        assert_eq!(engine.process(tx), Err(TransactionError::UnknownClient(1)));
+    }
+    assert_eq!(engine.accounts().count(), 0);
+    engine
+        .process(Transaction::Deposit(1, 2, amount("0")))
+        .unwrap();
+    assert_eq!(
+        account(&engine, 1),
+        &Client {
+            tx_count: 1,
+            ..Client::new(1, None)
+        }
+    );
+}
+
+#[test]
+fn opened_account_funds_can_be_withdrawn() {
+    let mut engine = TransactionEngine::new();
+    let opening = Client {
+        available: amount("5.0"),
+        held: amount("1.0"),
+        total: amount("6.0"),
+        ..Client::new(1, None)
+    };
+    engine.open_account(opening.clone()).unwrap();
+    assert_eq!(
+        engine.open_account(opening),
+        Err(TransactionError::AccountExists(1))
+    );
+    assert_eq!(
+        engine.open_account(Client {
+            available: amount("1.0"),
+            total: amount("2.0"),
+            ..Client::new(2, None)
+        }),
+        Err(TransactionError::InconsistentBalance {
+            total: amount("2.0"),
+            expected: amount("1.0"),
+        })
+    );
+    assert!(engine.get_account(2).is_none());
+    assert_eq!(engine.validate(), Ok(()));
+    engine
+        .process(Transaction::Withdrawal(1, 1, amount("4.5")))
+        .unwrap();
+    let client = account(&engine, 1);
+    assert_eq!(client.available, amount("0.5"));
+    assert_eq!(client.total, amount("1.5"));
+}
+
+#[test]
+fn rejected_withdrawal_dispute_keeps_the_withdrawal_record() {
+    let config = EngineConfig::new().allow_withdrawal_disputes(false);
+    let mut engine = TransactionEngine::with_config(config);
+    engine
+        .process(Transaction::Deposit(1, 1, amount("10.0")))
+        .unwrap();
+    engine
+        .process(Transaction::Withdrawal(1, 2, amount("4.0")))
+        .unwrap();
+    // A removed record would be reported as unknown on the second attempt
+    for _ in 0..2 {
+        assert_eq!(
+            engine.process(Transaction::Dispute(1, 2, None)),
+            Err(TransactionError::WithdrawalDisputeNotAllowed)
+        );
+    }
+    assert_eq!(
+        engine.process(Transaction::Resolve(1, 2)),
+        Err(TransactionError::NotDisputed)
+    );
+    let client = account(&engine, 1);
+    assert_eq!(client.available, amount("6.0"));
+    assert_eq!(client.held, amount("0"));
+    assert_eq!(client.total, amount("6.0"));
+    engine.process(Transaction::Dispute(1, 1, None)).unwrap();
+}
+
+#[test]
+fn interleaved_clients_update_their_own_accounts() {
+    let clients = run_transactions(vec![
+        Transaction::Deposit(1, 1, amount("1.0")),
+        Transaction::Deposit(2, 2, amount("2.0")),
+        Transaction::Deposit(1, 3, amount("3.0")),
+        Transaction::Deposit(1, 4, amount("4.0")),
+        Transaction::Withdrawal(2, 5, amount("0.5")),
+        Transaction::Dispute(1, 3, None),
+        Transaction::Dispute(2, 2, None),
+        Transaction::Resolve(1, 3),
+    ]);
+    assert_eq!(
+        clients,
+        [
+            peaked(client(1, "8.0", "0", false, 5), "3.0"),
+            client(2, "-0.5", "2.0", false, 3)
+        ]
+    );
+}
+
+#[test]
+fn open_disputes_add_up_to_the_held_funds() {
+    let mut engine = TransactionEngine::new();
+    let transactions = vec![
+        Transaction::Deposit(2, 1, amount("5.0")),
+        Transaction::Deposit(1, 2, amount("3.0")),
+        Transaction::Deposit(1, 3, amount("2.0")),
+        Transaction::Dispute(1, 3, Some(amount("0.5"))),
+        Transaction::Dispute(1, 2, None),
+        Transaction::Dispute(2, 1, None),
+        Transaction::Resolve(2, 1),
+    ];
+    for tx in transactions {
+        engine.process(tx).unwrap();
+    }
+    let disputes = engine.open_disputes();
+    let summary: Vec<(u16, u32, Amount)> = disputes
+        .iter()
+        .map(|dispute| (dispute.client_id, dispute.tx_id, dispute.amount))
+        .collect();
+    assert_eq!(summary, [(1, 2, amount("3.0")), (1, 3, amount("0.5"))]);
+    assert_eq!(account(&engine, 1).held, amount("3.5"));
+}
+
+#[test]
+fn dispute_window_forgets_the_oldest_transactions() {
+    let config = EngineConfig::new().dispute_window(Some(2));
+    let mut engine = TransactionEngine::with_config(config);
+    engine
+        .process(Transaction::Deposit(1, 1, amount("1.0")))
+        .unwrap();
+    engine
+        .process(Transaction::Deposit(1, 2, amount("2.0")))
+        .unwrap();
+    engine.process(Transaction::Dispute(1, 1, None)).unwrap();
+    engine
+        .process(Transaction::Deposit(1, 3, amount("3.0")))
+        .unwrap();
+    engine
+        .process(Transaction::Deposit(1, 4, amount("4.0")))
+        .unwrap();
+    // The disputed transaction 1 is kept, so the window is filled by it and the latest one
+    assert_eq!(
+        engine.process(Transaction::Dispute(1, 2, None)),
+        Err(TransactionError::UnknownTransaction)
+    );
+    assert_eq!(
+        engine.process(Transaction::Dispute(1, 3, None)),
+        Err(TransactionError::UnknownTransaction)
+    );
+    engine.process(Transaction::Resolve(1, 1)).unwrap();
+    engine.process(Transaction::Dispute(1, 4, None)).unwrap();
+    let client = account(&engine, 1);
+    assert_eq!(client.available, amount("6.0"));
+    assert_eq!(client.held, amount("4.0"));
+}
+
+#[test]
+fn idempotent_mode_ignores_exact_repeats_and_rejects_conflicting_ones() {
+    let config = EngineConfig::new().idempotent(true);
+    let mut engine = TransactionEngine::with_config(config);
+    for _ in 0..2 {
+        engine
+            .process(Transaction::Deposit(1, 1, amount("10.0")))
+            .unwrap();
+        engine
+            .process(Transaction::Withdrawal(1, 2, amount("4.0")))
+            .unwrap();
+    }
+    assert_eq!(
+        engine.process(Transaction::Deposit(1, 1, amount("11.0"))),
+        Err(TransactionError::ConflictingRepeat(1))
+    );
+    assert_eq!(
+        engine.process(Transaction::Deposit(2, 2, amount("4.0"))),
+        Err(TransactionError::ConflictingRepeat(2))
+    );
+    let client = account(&engine, 1);
+    assert_eq!(client.available, amount("6.0"));
+    assert_eq!(client.total, amount("6.0"));
+    assert_eq!(client.tx_count, 2);
+}
+
+#[test]
+fn observer_sees_every_accepted_transaction_with_its_account() {
+    let (sender, receiver) = std::sync::mpsc::channel();
+    let mut engine = TransactionEngine::new();
+    engine.set_observer(move |transaction, client| {
+        sender
+            .send((transaction.name(), client.id, client.available, client.held))
+            .unwrap();
+    });
+    engine
+        .process(Transaction::Deposit(1, 1, amount("3.0")))
+        .unwrap();
+    assert!(engine
+        .process(Transaction::Withdrawal(1, 2, amount("5.0")))
+        .is_err());
+    engine.process(Transaction::Dispute(1, 1, None)).unwrap();
+    engine.clear_observer();
+    engine.process(Transaction::Resolve(1, 1)).unwrap();
+    let observed: Vec<_> = receiver.iter().collect();
+    assert_eq!(
+        observed,
+        [
+            ("deposit", 1, amount("3.0"), amount("0")),
+            ("dispute", 1, amount("0"), amount("3.0"))
+        ]
+    );
+
+    // A parked dispute is only observed once applied, after the deposit it refers to
+    let (sender, receiver) = std::sync::mpsc::channel();
+    let config = EngineConfig::new().defer_early_disputes(true);
+    let mut engine = TransactionEngine::with_config(config);
+    engine.set_observer(move |transaction, client| {
+        sender
+            .send((transaction.name(), client.id, client.available, client.held))
+            .unwrap();
+    });
+    engine
+        .process(Transaction::Deposit(1, 1, amount("1.0")))
+        .unwrap();
+    engine.process(Transaction::Dispute(1, 2, None)).unwrap();
+    engine
+        .process(Transaction::Deposit(1, 2, amount("2.0")))
+        .unwrap();
+    drop(engine);
+    let observed: Vec<_> = receiver.iter().collect();
+    assert_eq!(
+        observed,
+        [
+            ("deposit", 1, amount("1.0"), amount("0")),
+            ("deposit", 1, amount("3.0"), amount("0")),
+            ("dispute", 1, amount("1.0"), amount("2.0"))
+        ]
+    );
+}
+
+/// Whole units without any decimals, standing in for a money type of a library user
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord)]
+struct Units(i32);
+
+impl fmt::Display for Units {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+impl Money for Units {
+    fn checked_add(self, other: Units) -> Option<Units> {
+        self.0.checked_add(other.0).map(Units)
+    }
+
+    fn checked_sub(self, other: Units) -> Option<Units> {
+        self.0.checked_sub(other.0).map(Units)
+    }
+}
+
+#[test]
+fn engine_runs_with_custom_money_type() {
+    let config = EngineConfig::default().overdraft(Units(2));
+    let mut engine = TransactionEngine::with_config(config);
+    engine
+        .process(Transaction::Deposit(1, 1, Units(10)))
+        .unwrap();
+    engine
+        .process(Transaction::Withdrawal(1, 2, Units(11)))
+        .unwrap();
+    engine
+        .process(Transaction::Dispute(1, 1, Some(Units(4))))
+        .unwrap();
+    let err = engine
+        .process(Transaction::Withdrawal(1, 3, Units(1)))
+        .unwrap_err();
+    assert_eq!(err.to_string(), "doesn't have enough funds (-5 < 1)");
+    engine.process(Transaction::Chargeback(1, 1, None)).unwrap();
+    let client = engine.get_account(1).unwrap();
+    assert_eq!(
+        (client.available, client.held, client.total),
+        (Units(-5), Units(0), Units(-5))
+    );
+    assert!(client.locked);
+}
+
+#[cfg(feature = "async")]
+#[test]
+fn stream_keeps_the_currencies_in_separate_accounts() {
+    use std::future::Future;
+    use std::task::{Context, Poll, Waker};
+    use transact::{process_stream, OperationInput};
+
+    let row = |op_type: &str, tx: u32, amount: &str, currency: &str| OperationInput {
+        op_type: op_type.to_string(),
+        client: 1,
+        tx,
+        amount: Some(amount.to_string()),
+        currency: Some(currency.to_string()),
+        timestamp: None,
+    };
+    let rows = vec![
+        row("deposit", 1, "5.0", "USD"),
+        row("deposit", 2, "3.0", "EUR"),
+        row("withdrawal", 3, "4.0", "EUR"),
+        row("withdrawal", 4, "1.0", "USD"),
+    ];
+    // The stream of a list is always ready, so a single poll drives it to the end
+    let future = std::pin::pin!(process_stream(futures_util::stream::iter(rows)));
+    let Poll::Ready(engine) = future.poll(&mut Context::from_waker(Waker::noop())) else {
+        panic!("the stream of a list should be processed at once");
+    };
+    let balances: Vec<_> = engine
+        .accounts()
+        .map(|cl| (cl.currency, cl.available))
+        .collect();
+    assert_eq!(
+        balances,
+        [
+            (Some("EUR".parse::<Currency>().unwrap()), amount("3.0")),
+            (Some("USD".parse::<Currency>().unwrap()), amount("4.0"))
+        ]
+    );
+}
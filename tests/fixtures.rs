@@ -0,0 +1,32 @@
+use std::fs;
+use std::path::Path;
+use std::process::Command;
+
+/// Runs every `input.csv` under the `tests/fixtures` directories through the binary, and compares
+/// the account list written with the `expected.csv` next to it, byte for byte. The rejected
+/// transactions are part of the fixtures, so only the output is checked, not the exit code.
+#[test]
+fn fixtures_produce_expected_accounts() {
+    let root = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures");
+    let mut fixtures: Vec<_> = fs::read_dir(&root)
+        .unwrap()
+        .map(|entry| entry.unwrap().path())
+        .filter(|path| path.join("input.csv").is_file())
+        .collect();
+    fixtures.sort();
+    assert!(!fixtures.is_empty(), "no fixtures in {}", root.display());
+    for fixture in fixtures {
+        let output = Command::new(env!("CARGO_BIN_EXE_transact"))
+            .arg("--quiet")
+            .arg(fixture.join("input.csv"))
+            .output()
+            .unwrap();
+        let expected = fs::read_to_string(fixture.join("expected.csv")).unwrap();
+        assert_eq!(
+            String::from_utf8(output.stdout).unwrap(),
+            expected,
+            "fixture {}",
+            fixture.display()
+        );
+    }
+}
@@ -0,0 +1,257 @@
+use transact::{InputConfig, InputError, OperationInput, Timestamp, Transaction};
+
+#[test]
+fn blank_or_zero_amount_of_dispute_rows_means_the_whole_transaction() {
+    let row = |op_type: &str, amount: Option<&str>| OperationInput {
+        op_type: op_type.to_string(),
+        client: 1,
+        tx: 7,
+        amount: amount.map(str::to_string),
+        currency: None,
+        timestamp: None,
+    };
+    for amount in [
+        None,
+        Some(""),
+        Some("  "),
+        Some("0"),
+        Some("0.0000"),
+        Some(" 0 "),
+    ] {
+        assert!(matches!(
+            row("dispute", amount).to_transaction(),
+            Ok(Transaction::Dispute(1, 7, None))
+        ));
+        assert!(matches!(
+            row("chargeback", amount).to_transaction(),
+            Ok(Transaction::Chargeback(1, 7, None))
+        ));
+    }
+    for amount in [None, Some("0"), Some("1.5"), Some("junk")] {
+        assert!(matches!(
+            row("resolve", amount).to_transaction(),
+            Ok(Transaction::Resolve(1, 7))
+        ));
+    }
+    assert!(matches!(
+        row("dispute", Some(" 1.5 ")).to_transaction(),
+        Ok(Transaction::Dispute(1, 7, Some(amount))) if amount == "1.5".parse().unwrap()
+    ));
+}
+
+#[test]
+fn transactions_display_and_serialize_with_four_decimals() {
+    let deposit = Transaction::Deposit(1, 10, "1.5".parse().unwrap());
+    let dispute: Transaction = Transaction::Dispute(1, 10, None);
+    assert_eq!(deposit.to_string(), "deposit client=1 tx=10 amount=1.5000");
+    assert_eq!(dispute.to_string(), "dispute client=1 tx=10");
+    assert_eq!(
+        serde_json::to_string(&[deposit, dispute]).unwrap(),
+        r#"[{"type":"deposit","client":1,"tx":10,"amount":"1.5000"},"#.to_string()
+            + r#"{"type":"dispute","client":1,"tx":10,"amount":null}]"#
+    );
+}
+
+#[test]
+fn locale_amounts_may_be_quoted_and_grouped() {
+    let deposit = |amount: &str| OperationInput {
+        op_type: "deposit".to_string(),
+        client: 1,
+        tx: 1,
+        amount: Some(amount.to_string()),
+        currency: None,
+        timestamp: None,
+    };
+    let config = InputConfig::new().locale_amounts(true);
+    for (text, expected) in [
+        ("\"1,000.50\"", "1000.5"),
+        ("'12,345,678'", "12345678"),
+        (" \"999.9999\" ", "999.9999"),
+        ("1000", "1000"),
+    ] {
+        assert!(
+            matches!(
+                deposit(text).to_transaction_with(&config),
+                Ok(Transaction::Deposit(1, 1, amount)) if amount == expected.parse().unwrap()
+            ),
+            "{text}"
+        );
+    }
+    for text in ["1,00.5", ",100", "1,0000", "1.000,5", "\"1,000.50"] {
+        assert_eq!(
+            deposit(text).to_transaction_with(&config).unwrap_err(),
+            InputError::InvalidAmount(text.to_string()),
+            "{text}"
+        );
+    }
+    assert!(deposit("\"1,000.50\"").to_transaction().is_err());
+}
+
+#[test]
+fn timestamps_compare_by_the_actual_time() {
+    let timestamp = |text: &str| text.parse::<Timestamp>().unwrap();
+    assert_eq!(
+        timestamp("2024-03-01T12:30:00+02:00"),
+        timestamp("2024-03-01 10:30Z")
+    );
+    assert!(timestamp("2024-02-29T23:59:59.999") < timestamp("2024-03-01"));
+    assert_eq!(timestamp("1970-01-01T00:00:01-0000").unix_seconds(), 1);
+    assert_eq!(
+        timestamp("2024-03-01T12:30:00.250+02:00").to_string(),
+        "2024-03-01T10:30:00.25Z"
+    );
+    for text in [
+        "2023-02-29",
+        "2024-03-01T24:00",
+        "2024-03-01T12:30+2",
+        "01/03/2024",
+    ] {
+        assert!(text.parse::<Timestamp>().is_err(), "{text}");
+    }
+}
+
+#[test]
+fn out_of_range_ids_are_named_in_the_error() {
+    let parse = |json: &str| serde_json::from_str::<OperationInput>(json).unwrap_err();
+    let err = parse(r#"{"type":"deposit","client":1,"tx":4294967296,"amount":"1"}"#);
+    assert!(err
+        .to_string()
+        .starts_with(&InputError::TxIdOutOfRange("4294967296".to_string()).to_string()));
+    let err = parse(r#"{"type":"deposit","client":-1,"tx":1,"amount":"1"}"#);
+    assert!(err
+        .to_string()
+        .starts_with("client ID -1 is out of range, the maximum is 65535"));
+    let record: OperationInput =
+        serde_json::from_str(r#"{"type":"deposit","client":65535,"tx":4294967295}"#).unwrap();
+    assert_eq!((record.client, record.tx), (65535, 4294967295));
+    // A string is the wrong type for an ID, even if it holds the digits of one
+    let err = parse(r#"{"type":"deposit","client":"1","tx":1,"amount":"1"}"#);
+    assert!(err
+        .to_string()
+        .starts_with(r#"invalid type: string "1", expected a client ID"#));
+
+    // The CSV reader would take an integer beyond the widest one for a floating point number
+    let csv_rows = "type,client,tx,amount
+                    deposit,1,100000000000000000000000000000000000000000,1
+                    deposit,1,1.5,1
+";
+    let mut reader = csv::Reader::from_reader(csv_rows.as_bytes());
+    let errors: Vec<String> = reader
+        .deserialize::<OperationInput>()
+        .map(|record| record.unwrap_err().to_string())
+        .collect();
+    assert!(errors[0].ends_with(
+        &InputError::TxIdOutOfRange("100000000000000000000000000000000000000000".to_string())
+            .to_string()
+    ));
+    assert!(errors[1].ends_with(r#"invalid value: string "1.5", expected a transaction ID"#));
+}
+
+#[test]
+fn invisible_characters_around_the_type_are_ignored() {
+    let row = |op_type: &str| OperationInput {
+        op_type: op_type.to_string(),
+        client: 1,
+        tx: 7,
+        amount: Some("1.5".to_string()),
+        currency: None,
+        timestamp: None,
+    };
+    for op_type in [
+        "deposit\u{200b}",
+        "\u{feff}Deposit",
+        "\u{a0}deposit\u{2060} ",
+    ] {
+        assert!(matches!(
+            row(op_type).to_transaction(),
+            Ok(Transaction::Deposit(1, 7, _))
+        ));
+    }
+    // The bytes of an unrecognized type show the characters hidden in it
+    let err = row("depo\u{200b}sit").to_transaction().unwrap_err();
+    assert_eq!(
+        err.to_string(),
+        "unknown operation depo\u{200b}sit (bytes 64 65 70 6f e2 80 8b 73 69 74)"
+    );
+    assert_eq!(
+        row("bogus").to_transaction().unwrap_err().to_string(),
+        "unknown operation bogus"
+    );
+}
+
+#[test]
+fn amounts_are_read_exactly_as_written() {
+    let csv_rows = "type,client,tx,amount\n\
+                    deposit,1,1,900719925474.0993\n\
+                    deposit,1,2,12345678901234.5678\n\
+                    deposit,1,3,1e3\n";
+    let mut reader = csv::Reader::from_reader(csv_rows.as_bytes());
+    let records: Vec<OperationInput> = reader.deserialize().map(Result::unwrap).collect();
+    let amounts: Vec<_> = records.iter().map(|r| r.amount.as_deref()).collect();
+    assert_eq!(
+        amounts,
+        [
+            Some("900719925474.0993"),
+            Some("12345678901234.5678"),
+            Some("1e3")
+        ]
+    );
+    assert!(matches!(
+        records[0].to_transaction(),
+        Ok(Transaction::Deposit(1, 1, amount)) if amount == "900719925474.0993".parse().unwrap()
+    ));
+    assert!(matches!(
+        records[1].to_transaction(),
+        Ok(Transaction::Deposit(1, 2, amount)) if amount == "12345678901234.5678".parse().unwrap()
+    ));
+    // A number in the exponent form is not a decimal amount
+    assert_eq!(
+        records[2].to_transaction().unwrap_err(),
+        InputError::InvalidAmount("1e3".to_string())
+    );
+
+    // The JSON numbers keep their digits as well, while the strings are unescaped
+    let parse = |json: &str| serde_json::from_str::<OperationInput>(json).unwrap().amount;
+    assert_eq!(
+        parse(r#"{"type":"deposit","client":1,"tx":1,"amount":900719925474.0993}"#).as_deref(),
+        Some("900719925474.0993")
+    );
+    assert_eq!(
+        parse(r#"{"type":"deposit","client":1,"tx":1,"amount":"1.50"}"#).as_deref(),
+        Some("1.50")
+    );
+    assert_eq!(
+        parse(r#"{"type":"dispute","client":1,"tx":1,"amount":null}"#),
+        None
+    );
+    let record: OperationInput =
+        serde_json::from_str(r#"{"type":"deposit","client":1,"tx":1,"amount":1e3}"#).unwrap();
+    assert_eq!(
+        record.to_transaction().unwrap_err(),
+        InputError::InvalidAmount("1e3".to_string())
+    );
+}
+
+#[test]
+fn strict_precision_rejects_more_than_four_decimals() {
+    let deposit = |amount: &str| OperationInput {
+        op_type: "deposit".to_string(),
+        client: 1,
+        tx: 1,
+        amount: Some(amount.to_string()),
+        currency: None,
+        timestamp: None,
+    };
+    let strict = InputConfig::new().strict_precision(true);
+    let err = deposit("1.23456").to_transaction_with(&strict).unwrap_err();
+    assert_eq!(err, InputError::TooPrecise("1.23456".to_string()));
+    assert_eq!(err.code(), "too_precise");
+    assert!(matches!(
+        deposit("1.2345").to_transaction_with(&strict),
+        Ok(Transaction::Deposit(1, 1, amount)) if amount == "1.2345".parse().unwrap()
+    ));
+    assert!(matches!(
+        deposit("1.23456").to_transaction(),
+        Ok(Transaction::Deposit(1, 1, amount)) if amount == "1.2345".parse().unwrap()
+    ));
+}